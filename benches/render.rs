@@ -0,0 +1,151 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use erst::{Escaper, HtmlEscaper, Template};
+use std::fmt::{Display, Write as _};
+
+pub struct Row {
+    name: String,
+    email: &'static str,
+    note: String,
+    visits: u32,
+}
+
+#[derive(Template)]
+#[template(
+    source = "<table><% for row in self.0 { %><tr><td><%= row.name %></td><td><%= row.email %></td><td><%= row.note %></td><td><%= row.visits %></td></tr><% } %></table>",
+    type = "html",
+    size_hint = 1048576
+)]
+pub struct Table<'a>(&'a [Row]);
+
+/// The HTML escaping from before 0.4, which went through `io::Write` and bstr, kept as a baseline
+struct OldHtml<T>(T);
+
+struct OldHtmlWriter<'a, 'b: 'a>(&'a mut std::fmt::Formatter<'b>);
+
+impl OldHtmlWriter<'_, '_> {
+    fn write_slice(&mut self, bytes: &[u8]) -> std::fmt::Result {
+        let bstr = erst_shared::exp::B(bytes);
+        if let Ok(s) = bstr.to_str() {
+            self.0.write_str(s)?;
+        } else {
+            for chr in bstr.chars() {
+                self.0.write_char(chr)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::io::Write for OldHtmlWriter<'_, '_> {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        use std::io::Error;
+
+        let mut from = 0;
+
+        for (idx, byte) in bytes.iter().enumerate() {
+            if !(b'"'..=b'>').contains(byte) {
+                continue;
+            }
+
+            let rep_opt = match byte {
+                b'<' => Some("&lt;"),
+                b'>' => Some("&gt;"),
+                b'&' => Some("&amp;"),
+                b'"' => Some("&quot;"),
+                b'\'' => Some("&#x27;"),
+                b'/' => Some("&#x2f;"),
+                _ => None,
+            };
+
+            if let Some(rep) = rep_opt {
+                self.write_slice(&bytes[from..idx]).map_err(Error::other)?;
+                self.0.write_str(rep).map_err(Error::other)?;
+                from = idx + 1;
+            }
+        }
+
+        self.write_slice(&bytes[from..]).map_err(Error::other)?;
+
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<T> Display for OldHtml<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use std::io::Write;
+        write!(OldHtmlWriter(f), "{}", &self.0).map_err(|_| std::fmt::Error)
+    }
+}
+
+fn rows(count: usize) -> Vec<Row> {
+    (0..count)
+        .map(|idx| Row {
+            name: format!("Customer number {}", idx),
+            email: "someone@example.com",
+            note: if idx % 10 == 0 {
+                format!("<b>\"Tom & Jerry's\"</b> row {}", idx)
+            } else {
+                format!("a plain note without special characters, row {}", idx)
+            },
+            visits: idx as u32,
+        })
+        .collect()
+}
+
+fn escape(c: &mut Criterion) {
+    let plain = "a plain note without special characters ".repeat(64);
+    let special = "<b>\"Tom & Jerry's\"</b> / ".repeat(64);
+
+    let mut group = c.benchmark_group("html_escape");
+
+    for (name, input) in &[("plain", &plain), ("special", &special)] {
+        group.throughput(Throughput::Bytes(input.len() as u64));
+
+        group.bench_function(format!("{}/old", name), |b| {
+            let mut out = String::with_capacity(input.len() * 2);
+            b.iter(|| {
+                out.clear();
+                write!(out, "{}", OldHtml(input.as_str())).unwrap();
+            })
+        });
+
+        // Through `Display`, as for any value that isn't a string
+        group.bench_function(format!("{}/display", name), |b| {
+            let mut out = String::with_capacity(input.len() * 2);
+            b.iter(|| {
+                out.clear();
+                HtmlEscaper.write_value(&input.as_str(), &mut out).unwrap();
+            })
+        });
+
+        group.bench_function(format!("{}/str", name), |b| {
+            let mut out = String::with_capacity(input.len() * 2);
+            b.iter(|| {
+                out.clear();
+                HtmlEscaper.write_str(input, &mut out).unwrap();
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn table(c: &mut Criterion) {
+    let rows = rows(10_000);
+    let table = Table(&rows);
+
+    let mut group = c.benchmark_group("render");
+    group.throughput(Throughput::Elements(rows.len() as u64));
+    group.bench_function("table", |b| b.iter(|| table.render().unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, escape, table);
+criterion_main!(benches);
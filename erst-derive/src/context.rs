@@ -0,0 +1,354 @@
+//! Picks the escaper for each expression in an `html` template from its HTML context.
+//!
+//! Only the template's text is scanned: code and `<%== %>` output are assumed not to change the context, so e.g. a
+//! tag left open in one branch of an `if` isn't noticed.
+
+use erst_shared::parser::{Kind, Segment, Template};
+
+/// Attributes whose values are URLs
+const URL_ATTRS: &[&str] = &[
+    "action",
+    "background",
+    "cite",
+    "codebase",
+    "data",
+    "formaction",
+    "href",
+    "icon",
+    "longdesc",
+    "manifest",
+    "ping",
+    "poster",
+    "src",
+    "srcset",
+    "usemap",
+    "xlink:href",
+];
+
+/// Elements whose content isn't parsed as HTML, up to their end tag
+const RAW_TEXT: &[&str] = &["script", "style", "textarea", "title"];
+
+/// The escaper for `<script>` code outside of strings, whose expressions are written as JSON literals instead
+pub(crate) const JS_VALUE: &str = "erst::JsValueEscaper";
+
+/// Where we are in an HTML document
+enum State {
+    /// Element content, or a comment
+    Text {
+        comment: bool,
+    },
+    /// After `<`, before we know it starts a tag
+    TagOpen,
+    TagName {
+        name: String,
+        end: bool,
+    },
+    /// Between attributes
+    Tag {
+        name: String,
+        end: bool,
+    },
+    AttrName {
+        tag: String,
+        attr: String,
+    },
+    AfterAttrName {
+        tag: String,
+        attr: String,
+    },
+    BeforeValue {
+        tag: String,
+        attr: String,
+    },
+    Value {
+        tag: String,
+        quote: Option<char>,
+        value: Value,
+    },
+    /// The content of a `script`, `style`, `textarea` or `title` element
+    RawText {
+        tag: String,
+        js: Js,
+    },
+}
+
+/// What an attribute value holds
+enum Value {
+    Plain,
+    Url { started: bool },
+    Js(Js),
+    Css,
+}
+
+/// Keywords after which a `/` starts a regular expression instead of dividing
+const REGEX_KEYWORDS: &[&str] = &[
+    "await",
+    "case",
+    "delete",
+    "do",
+    "else",
+    "in",
+    "instanceof",
+    "new",
+    "of",
+    "return",
+    "throw",
+    "typeof",
+    "void",
+    "yield",
+];
+
+/// Where we are in JavaScript code, enough to tell whether an expression is inside a string, regular expression or
+/// comment
+#[derive(Clone)]
+enum Js {
+    /// `regex` is whether a `/` here starts a regular expression (rather than dividing), and `word` the identifier,
+    /// keyword or number being read
+    Code {
+        regex: bool,
+        word: String,
+    },
+    Slash {
+        regex: bool,
+    },
+    Str {
+        quote: char,
+        escaped: bool,
+    },
+    Regex {
+        escaped: bool,
+        class: bool,
+    },
+    LineComment,
+    BlockComment {
+        star: bool,
+    },
+}
+
+impl Js {
+    fn code(regex: bool) -> Js {
+        Js::Code { regex, word: String::new() }
+    }
+
+    fn next(self, chr: char) -> Js {
+        match self {
+            Js::Code { regex, mut word } if chr.is_alphanumeric() || chr == '_' || chr == '$' => {
+                word.push(chr);
+                Js::Code { regex, word }
+            }
+            Js::Code { regex, word } => {
+                let regex =
+                    if word.is_empty() { regex } else { REGEX_KEYWORDS.contains(&word.as_str()) };
+                match chr {
+                    '/' => Js::Slash { regex },
+                    '"' | '\'' | '`' => Js::Str { quote: chr, escaped: false },
+                    chr if chr.is_whitespace() => Js::code(regex),
+                    ')' | ']' => Js::code(false),
+                    _ => Js::code(true),
+                }
+            }
+            Js::Slash { .. } if chr == '/' => Js::LineComment,
+            Js::Slash { .. } if chr == '*' => Js::BlockComment { star: false },
+            Js::Slash { regex: true } => Js::Regex { escaped: false, class: false }.next(chr),
+            Js::Slash { regex: false } => Js::code(true).next(chr),
+            Js::Str { quote, escaped: true } => Js::Str { quote, escaped: false },
+            Js::Str { quote, .. } if chr == '\\' => Js::Str { quote, escaped: true },
+            Js::Str { quote, .. } if chr == quote => Js::code(false),
+            Js::Str { .. } => self,
+            Js::Regex { class, escaped: true } => Js::Regex { class, escaped: false },
+            Js::Regex { class, .. } if chr == '\\' => Js::Regex { class, escaped: true },
+            Js::Regex { .. } if chr == '[' => Js::Regex { class: true, escaped: false },
+            Js::Regex { class: true, .. } if chr == ']' => {
+                Js::Regex { class: false, escaped: false }
+            }
+            Js::Regex { class: false, .. } if chr == '/' => Js::code(false),
+            Js::Regex { .. } => self,
+            Js::LineComment if chr == '\n' => Js::code(true),
+            Js::LineComment => self,
+            Js::BlockComment { star: true } if chr == '/' => Js::code(true),
+            Js::BlockComment { .. } => Js::BlockComment { star: chr == '*' },
+        }
+    }
+
+    fn in_string(&self) -> bool {
+        !matches!(self, Js::Code { .. } | Js::Slash { regex: false })
+    }
+}
+
+/// Returns the escaper to use for each `<%= %>` tag in `template`, in order
+pub(crate) fn escapers(template: &Template) -> Result<Vec<&'static str>, String> {
+    let mut state = State::Text { comment: false };
+    let mut out = Vec::new();
+
+    for segment in &template.segments {
+        match segment.kind {
+            Kind::Text => state = scan(state, &segment.content),
+            Kind::Expr => {
+                let escaper = escaper(&mut state).map_err(|msg| error(template, segment, msg))?;
+                out.push(escaper);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(out)
+}
+
+fn error(template: &Template, segment: &Segment, msg: &str) -> String {
+    template.annotate(segment, crate::diagnostic::start_of(segment), msg)
+}
+
+/// Picks the escaper for an expression at `state`, updating the state for its output
+fn escaper(state: &mut State) -> Result<&'static str, &'static str> {
+    match state {
+        State::Text { comment: false } => Ok("erst::HtmlEscaper"),
+        State::Text { comment: true } => Ok("erst::HtmlAttrEscaper"),
+        State::RawText { tag, js } => match tag.as_str() {
+            "script" if js.in_string() => Ok("erst::JsEscaper"),
+            "script" => {
+                *js = Js::code(false);
+                Ok(JS_VALUE)
+            }
+            "style" => Ok("erst::CssEscaper"),
+            _ => Ok("erst::HtmlAttrEscaper"),
+        },
+        State::Value { quote: None, .. } | State::BeforeValue { .. } => {
+            Err("expressions can't be used in unquoted attribute values")
+        }
+        State::Value { value, .. } => match value {
+            Value::Plain => Ok("erst::HtmlAttrEscaper"),
+            Value::Url { started: false } => {
+                *value = Value::Url { started: true };
+                Ok("erst::HtmlUrlEscaper")
+            }
+            Value::Url { started: true } => Ok("erst::HtmlUrlComponentEscaper"),
+            Value::Js(js) if js.in_string() => Ok("erst::JsEscaper"),
+            Value::Js(_) => {
+                Err("expressions in event handler attributes must be inside a JavaScript string")
+            }
+            Value::Css => Ok("erst::CssEscaper"),
+        },
+        _ => Err("expressions can't be used inside a tag, except in quoted attribute values"),
+    }
+}
+
+/// Advances `state` over the template text `text`
+fn scan(mut state: State, text: &str) -> State {
+    let mut rest = text;
+
+    while let Some(chr) = rest.chars().next() {
+        let mut skip = chr.len_utf8();
+
+        state = match state {
+            State::Text { comment: true } if rest.starts_with("-->") => {
+                skip = 3;
+                State::Text { comment: false }
+            }
+            State::Text { comment: true } => State::Text { comment: true },
+            State::Text { comment: false } if rest.starts_with("<!--") => {
+                skip = 4;
+                State::Text { comment: true }
+            }
+            State::Text { comment: false } if chr == '<' => State::TagOpen,
+            State::Text { comment: false } => State::Text { comment: false },
+            State::TagOpen if chr.is_ascii_alphabetic() => {
+                State::TagName { name: chr.to_ascii_lowercase().to_string(), end: false }
+            }
+            State::TagOpen if chr == '/' => State::TagName { name: String::new(), end: true },
+            State::TagOpen => State::Text { comment: false },
+            State::TagName { mut name, end } => match chr {
+                '>' => close(name, end),
+                chr if chr.is_whitespace() || chr == '/' => State::Tag { name, end },
+                chr => {
+                    name.push(chr.to_ascii_lowercase());
+                    State::TagName { name, end }
+                }
+            },
+            State::Tag { name, end } => match chr {
+                '>' => close(name, end),
+                chr if chr.is_whitespace() || chr == '/' => State::Tag { name, end },
+                chr => State::AttrName { tag: name, attr: chr.to_ascii_lowercase().to_string() },
+            },
+            State::AttrName { tag, mut attr } => match chr {
+                '>' => close(tag, false),
+                '=' => State::BeforeValue { tag, attr },
+                '/' => State::Tag { name: tag, end: false },
+                chr if chr.is_whitespace() => State::AfterAttrName { tag, attr },
+                chr => {
+                    attr.push(chr.to_ascii_lowercase());
+                    State::AttrName { tag, attr }
+                }
+            },
+            State::AfterAttrName { tag, attr } => match chr {
+                '>' => close(tag, false),
+                '=' => State::BeforeValue { tag, attr },
+                '/' => State::Tag { name: tag, end: false },
+                chr if chr.is_whitespace() => State::AfterAttrName { tag, attr },
+                chr => State::AttrName { tag, attr: chr.to_ascii_lowercase().to_string() },
+            },
+            State::BeforeValue { tag, attr } => match chr {
+                '>' => close(tag, false),
+                '"' | '\'' => State::Value { tag, quote: Some(chr), value: value(&attr) },
+                chr if chr.is_whitespace() => State::BeforeValue { tag, attr },
+                chr => State::Value { tag, quote: None, value: value(&attr).next(chr) },
+            },
+            State::Value { tag, quote: Some(quote), .. } if chr == quote => {
+                State::Tag { name: tag, end: false }
+            }
+            State::Value { tag, quote: None, .. } if chr == '>' => close(tag, false),
+            State::Value { tag, quote: None, .. } if chr.is_whitespace() => {
+                State::Tag { name: tag, end: false }
+            }
+            State::Value { tag, quote, value } => {
+                State::Value { tag, quote, value: value.next(chr) }
+            }
+            State::RawText { tag, js } => {
+                let end = format!("</{}", tag);
+                match rest.get(..end.len()) {
+                    Some(prefix) if prefix.eq_ignore_ascii_case(&end) => {
+                        skip = end.len();
+                        State::TagName { name: tag, end: true }
+                    }
+                    _ => {
+                        State::RawText { js: if tag == "script" { js.next(chr) } else { js }, tag }
+                    }
+                }
+            }
+        };
+
+        rest = &rest[skip..];
+    }
+
+    state
+}
+
+/// The state after the `>` of a tag
+fn close(name: String, end: bool) -> State {
+    if !end && RAW_TEXT.contains(&name.as_str()) {
+        State::RawText { tag: name, js: Js::code(true) }
+    } else {
+        State::Text { comment: false }
+    }
+}
+
+fn value(attr: &str) -> Value {
+    if attr.starts_with("on") {
+        Value::Js(Js::code(true))
+    } else if attr == "style" {
+        Value::Css
+    } else if URL_ATTRS.contains(&attr) {
+        Value::Url { started: false }
+    } else {
+        Value::Plain
+    }
+}
+
+impl Value {
+    fn next(self, chr: char) -> Value {
+        match self {
+            Value::Url { .. } => Value::Url { started: true },
+            Value::Js(js) => Value::Js(js.next(chr)),
+            other => other,
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! Maps errors in the generated template body back to the template source.
+//!
+//! Spans produced by `syn::parse_str` all point at the derive, so when the glued body fails to parse we
+//! re-check the template's code pieces individually to find the one responsible.
+
+use erst_shared::parser::{Kind, Segment, Template};
+
+/// The file index, byte offset and message of an error in a template
+type Found = (usize, usize, String);
+
+/// Checks the code pieces of a template before they are glued together and handed to `syn`
+///
+/// Unbalanced delimiters have to be caught here: the compiler's lexer does not recover from them.
+pub(crate) fn check(template: &Template) -> Result<(), String> {
+    let mut delims = Delims::default();
+
+    for segment in template.segments.iter().filter(|x| x.kind != Kind::Text) {
+        let found = match segment.kind {
+            Kind::Expr | Kind::RawExpr => check_expr(segment),
+            _ => delims.scan(&segment.content, segment.file, segment.offset),
+        };
+
+        if let Some(found) = found {
+            return Err(render(template, found));
+        }
+    }
+
+    match delims.unclosed() {
+        Some(found) => Err(render(template, found)),
+        None => Ok(()),
+    }
+}
+
+/// Builds an error message for `error` pointing at the offending location in the template, if one can be found
+pub(crate) fn locate(template: &Template, pieces: &[String], error: syn::Error) -> String {
+    match find(&template.segments, pieces) {
+        Some(found) => render(template, found),
+        None => format!("{}: {}", template.sources[0].path.display(), error),
+    }
+}
+
+fn check_expr(segment: &Segment) -> Option<Found> {
+    let mut delims = Delims::default();
+    if let Some(found) =
+        delims.scan(&segment.content, segment.file, segment.offset).or_else(|| delims.unclosed())
+    {
+        return Some(found);
+    }
+
+    syn::parse_str::<syn::Expr>(&segment.content)
+        .err()
+        .map(|e| (segment.file, start_of(segment), e.to_string()))
+}
+
+/// Splits the template into runs of pieces that leave no delimiter open and parses each run
+fn find(segments: &[Segment], pieces: &[String]) -> Option<Found> {
+    let mut delims = Delims::default();
+    let mut from = 0;
+
+    for (idx, segment) in segments.iter().enumerate() {
+        if segment.kind != Kind::Code {
+            continue;
+        }
+
+        delims.scan(&segment.content, segment.file, segment.offset);
+
+        if delims.stack.is_empty() {
+            if let Some(found) = check_group(&segments[from..=idx], &pieces[from..=idx]) {
+                return Some(found);
+            }
+            from = idx + 1;
+        }
+    }
+
+    None
+}
+
+fn check_group(segments: &[Segment], pieces: &[String]) -> Option<Found> {
+    let error = syn::parse_str::<syn::Block>(&format!("{{ {} }}", pieces.concat())).err()?;
+
+    for segment in segments.iter().filter(|x| x.kind == Kind::Code) {
+        let mut delims = Delims::default();
+        if segment.content.trim_start().starts_with("else")
+            || delims.scan(&segment.content, 0, 0).is_some()
+            || !delims.stack.is_empty()
+        {
+            continue;
+        }
+
+        // Something always follows a code piece, so a trailing expression without `;` is an error
+        if let Err(e) = syn::parse_str::<syn::Block>(&format!("{{ {}\n{{}} }}", segment.content)) {
+            return Some((segment.file, start_of(segment), e.to_string()));
+        }
+    }
+
+    segments
+        .iter()
+        .find(|x| x.kind == Kind::Code)
+        .map(|segment| (segment.file, start_of(segment), error.to_string()))
+}
+
+pub(crate) fn start_of(segment: &Segment) -> usize {
+    let content = &segment.content;
+    segment.offset + (content.len() - content.trim_start().len())
+}
+
+fn render(template: &Template, (file, offset, msg): Found) -> String {
+    let source = &template.sources[file];
+    erst_shared::parser::annotate(&source.path.display().to_string(), &source.text, offset, &msg)
+}
+
+/// A minimal Rust lexer that tracks open delimiters across code pieces
+#[derive(Default)]
+struct Delims {
+    stack: Vec<(char, usize, usize)>,
+}
+
+impl Delims {
+    /// Scans `content` (starting at `base` in template `file`), returning an error for any unbalanced closing delimiter
+    fn scan(&mut self, content: &str, file: usize, base: usize) -> Option<Found> {
+        let chars = content.char_indices().collect::<Vec<_>>();
+        let mut idx = 0;
+
+        let at = |idx: usize| chars.get(idx).map(|x| x.1);
+
+        while let Some(chr) = at(idx) {
+            let offset = base + chars[idx].0;
+
+            match chr {
+                '/' if at(idx + 1) == Some('/') => {
+                    while at(idx).map(|x| x != '\n').unwrap_or(false) {
+                        idx += 1;
+                    }
+                }
+                '/' if at(idx + 1) == Some('*') => {
+                    let mut depth = 0;
+                    while let Some(chr) = at(idx) {
+                        if chr == '/' && at(idx + 1) == Some('*') {
+                            depth += 1;
+                            idx += 1;
+                        } else if chr == '*' && at(idx + 1) == Some('/') {
+                            depth -= 1;
+                            idx += 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        idx += 1;
+                    }
+                }
+                '"' => {
+                    idx += 1;
+                    while let Some(chr) = at(idx) {
+                        match chr {
+                            '\\' => idx += 1,
+                            '"' => break,
+                            _ => {}
+                        }
+                        idx += 1;
+                    }
+                }
+                'r' if !is_ident(idx.checked_sub(1).and_then(at))
+                    && matches!(at(idx + 1), Some('#') | Some('"')) =>
+                {
+                    let hashes = (idx + 1..).take_while(|x| at(*x) == Some('#')).count();
+                    if at(idx + 1 + hashes) == Some('"') {
+                        idx += hashes + 2;
+                        while let Some(chr) = at(idx) {
+                            if chr == '"' && (1..=hashes).all(|x| at(idx + x) == Some('#')) {
+                                idx += hashes;
+                                break;
+                            }
+                            idx += 1;
+                        }
+                    }
+                }
+                '\'' => {
+                    if at(idx + 1) == Some('\\') {
+                        idx += 3;
+                        while at(idx).map(|x| x != '\'').unwrap_or(false) {
+                            idx += 1;
+                        }
+                    } else if at(idx + 2) == Some('\'') {
+                        idx += 2;
+                    }
+                }
+                '(' | '[' | '{' => self.stack.push((chr, file, offset)),
+                ')' | ']' | '}' => match self.stack.pop() {
+                    Some((open, _, _)) if closer(open) == chr => {}
+                    Some(_) => {
+                        return Some((
+                            file,
+                            offset,
+                            format!("mismatched closing delimiter: `{}`", chr),
+                        ))
+                    }
+                    None => {
+                        return Some((
+                            file,
+                            offset,
+                            format!("unexpected closing delimiter: `{}`", chr),
+                        ))
+                    }
+                },
+                _ => {}
+            }
+
+            idx += 1;
+        }
+
+        None
+    }
+
+    fn unclosed(&self) -> Option<Found> {
+        self.stack
+            .last()
+            .map(|(chr, file, offset)| (*file, *offset, format!("unclosed delimiter: `{}`", chr)))
+    }
+}
+
+fn is_ident(chr: Option<char>) -> bool {
+    chr.map(|x| x.is_alphanumeric() || x == '_').unwrap_or(false)
+}
+
+fn closer(open: char) -> char {
+    match open {
+        '(' => ')',
+        '[' => ']',
+        _ => '}',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn templates_dir() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../examples/templates")
+    }
+
+    #[test]
+    fn expression_errors() {
+        let template = Template::resolve(&templates_dir(), "ui/syntax_error.erst").unwrap();
+        let msg = check(&template).unwrap_err();
+        assert!(msg.contains("ui/syntax_error.erst:3:17\n"), "{}", msg);
+        assert!(msg.contains("3 |         <li><%= x. %></li>\n"), "{}", msg);
+    }
+
+    #[test]
+    fn code_errors() {
+        let template = Template::from_source(
+            &templates_dir(),
+            "<Page source>",
+            "<p>\n  <% let x = ; %>\n</p>",
+        )
+        .unwrap();
+        check(&template).unwrap();
+
+        let pieces = crate::generate(&template, &["<Page source>".to_string()], None, None);
+        let error =
+            syn::parse_str::<syn::Block>(&format!("{{ {} }}", pieces.concat())).unwrap_err();
+        let msg = locate(&template, &pieces, error);
+        assert!(msg.contains("<Page source>:2:6\n"), "{}", msg);
+    }
+}
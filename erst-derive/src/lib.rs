@@ -6,213 +6,465 @@ extern crate quote;
 #[macro_use]
 extern crate syn;
 
+mod context;
+mod diagnostic;
+
+use erst_shared::parser::{Kind, Template};
 use proc_macro::TokenStream;
 use std::convert::TryFrom;
 
 #[proc_macro_derive(Template, attributes(template))]
 pub fn template_derive(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as syn::DeriveInput);
-    template_derive_inner(input).unwrap()
+    template_derive_inner(input).unwrap_or_else(|e| e.to_compile_error().into())
 }
 
-fn template_derive_inner(input: syn::DeriveInput) -> Result<TokenStream, Box<dyn std::error::Error>> {
-    let name = &input.ident;
-    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+/// The `key = "value"` pairs given in `#[template(...)]`
+#[derive(Default)]
+struct Attributes {
+    path: Option<syn::LitStr>,
+    source: Option<syn::LitStr>,
+    type_: Option<syn::LitStr>,
+    escape: Option<syn::LitStr>,
+    size_hint: Option<syn::LitInt>,
+    delimiter: Option<syn::LitChar>,
+    error: Option<syn::LitStr>,
+}
 
-    let mut path = None;
-    let mut type_ = None;
-    let mut size_hint = None;
+impl Attributes {
+    fn parse(input: &syn::DeriveInput) -> syn::Result<Self> {
+        let mut out = Attributes::default();
+        let mut found = false;
 
-    for pair in input
-        .attrs
-        .iter()
-        .flat_map(|x| x.parse_meta())
-        .filter(|x| x.name() == "template")
-        .filter_map(|x| match x {
-            syn::Meta::List(ml) => Some(ml),
-            _ => None,
-        })
-        .flat_map(|x| x.nested)
-        .filter_map(|x| match x {
-            syn::NestedMeta::Meta(m) => Some(m),
-            _ => None,
-        })
-        .filter_map(|x| match x {
-            syn::Meta::NameValue(nv) => Some(nv),
-            _ => None,
-        })
-    {
-        if pair.ident == "path" {
-            if let syn::Lit::Str(ref s) = pair.lit {
-                path = Some(s.value());
+        for attr in input.attrs.iter().filter(|x| x.path.segments.len() == 1) {
+            if attr.path.segments[0].ident != "template" {
+                continue;
             }
-        }
 
-        if pair.ident == "type" {
-            if let syn::Lit::Str(ref s) = pair.lit {
-                type_ = Some(s.value());
+            found = true;
+
+            let list = match attr.parse_meta()? {
+                syn::Meta::List(ml) => ml,
+                other => {
+                    return Err(syn::Error::new_spanned(
+                        other,
+                        "expected `#[template(path = \"...\")]`",
+                    ))
+                }
+            };
+
+            for nested in list.nested {
+                let pair = match nested {
+                    syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) => nv,
+                    other => {
+                        return Err(syn::Error::new_spanned(
+                            other,
+                            "expected `key = value` in #[template(...)]",
+                        ))
+                    }
+                };
+
+                match (pair.ident.to_string().as_str(), pair.lit) {
+                    ("path", syn::Lit::Str(s)) => out.path = Some(s),
+                    ("source", syn::Lit::Str(s)) => out.source = Some(s),
+                    ("type", syn::Lit::Str(s)) => out.type_ = Some(s),
+                    ("escape", syn::Lit::Str(s)) => out.escape = Some(s),
+                    ("error", syn::Lit::Str(s)) => out.error = Some(s),
+                    ("size_hint", syn::Lit::Int(i)) => out.size_hint = Some(i),
+                    ("delimiter", syn::Lit::Char(c)) => out.delimiter = Some(c),
+                    ("path", lit) | ("source", lit) | ("type", lit) | ("escape", lit)
+                    | ("error", lit) => {
+                        return Err(syn::Error::new_spanned(lit, "expected a string literal"))
+                    }
+                    ("size_hint", lit) => {
+                        return Err(syn::Error::new_spanned(lit, "expected an integer literal"))
+                    }
+                    ("delimiter", lit) => {
+                        return Err(syn::Error::new_spanned(lit, "expected a character literal"))
+                    }
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            &pair.ident,
+                            format!(
+                                "unknown template attribute `{}`, expected one of `path`, `source`, `type`, `escape`, `size_hint`, `delimiter`, `error`",
+                                pair.ident
+                            ),
+                        ))
+                    }
+                }
             }
         }
 
-        if pair.ident == "size_hint" {
-            if let syn::Lit::Int(i) = pair.lit {
-                size_hint = Some(i.value());
-            }
+        if !found {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "missing `#[template(path = \"...\")]` attribute",
+            ));
+        }
+
+        Ok(out)
+    }
+}
+
+/// The built-in template types and their escapers
+const TYPES: &[(&str, &str)] = &[
+    ("html", "erst::HtmlEscaper"),
+    ("xml", "erst::XmlEscaper"),
+    ("json", "erst::JsonEscaper"),
+    ("js", "erst::JsEscaper"),
+    ("css", "erst::CssEscaper"),
+    ("url", "erst::UrlComponentEscaper"),
+    ("sh", "erst::ShellEscaper"),
+    ("sql", "erst::SqlEscaper"),
+    ("rust", "erst::RustEscaper"),
+    ("latex", "erst::LatexEscaper"),
+    ("markdown", "erst::MarkdownEscaper"),
+    ("csv", "erst::CsvEscaper"),
+];
+
+fn template_derive_inner(input: syn::DeriveInput) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let attributes = Attributes::parse(&input)?;
+
+    let html = attributes.type_.as_ref().map(|x| x.value() == "html").unwrap_or(false);
+    let csv = attributes.type_.as_ref().map(|x| x.value() == "csv").unwrap_or(false);
+
+    if let Some(ref delimiter) = attributes.delimiter {
+        if !csv {
+            return Err(syn::Error::new_spanned(
+                delimiter,
+                "`delimiter` can only be used with `type = \"csv\"`",
+            ));
+        }
+        if matches!(delimiter.value(), '"' | '\r' | '\n') {
+            return Err(syn::Error::new_spanned(
+                delimiter,
+                "the delimiter can't be a quote or a line break",
+            ));
         }
     }
 
-    let type_ = type_.as_ref().map(|x| x.as_str()).unwrap_or_else(|| "");
+    let escaper = match (attributes.type_, attributes.escape) {
+        (Some(_), Some(escape)) => {
+            return Err(syn::Error::new_spanned(
+                escape,
+                "`type` and `escape` can't be used together",
+            ))
+        }
+        (Some(type_), None) => match type_.value().as_str() {
+            "" => None,
+            value => match TYPES.iter().find(|x| x.0 == value) {
+                Some((_, escaper)) => Some(syn::parse_str::<syn::Path>(escaper)?),
+                None => {
+                    let expected = TYPES.iter().map(|x| format!("`{}`", x.0)).collect::<Vec<_>>();
+                    return Err(syn::Error::new_spanned(
+                        type_,
+                        format!(
+                            "unknown template type `{}`, expected one of {}",
+                            value,
+                            expected.join(", ")
+                        ),
+                    ));
+                }
+            },
+        },
+        (None, Some(escape)) => Some(
+            escape
+                .parse::<syn::Path>()
+                .map_err(|_| syn::Error::new_spanned(escape, "expected a path to an escaper"))?,
+        ),
+        (None, None) => None,
+    };
+
+    let error = match attributes.error {
+        Some(error) => Some(
+            error
+                .parse::<syn::Path>()
+                .map_err(|_| syn::Error::new_spanned(error, "expected a path to an error type"))?,
+        ),
+        None => None,
+    };
+
+    let size_hint: usize = match attributes.size_hint {
+        Some(ref lit) => usize::try_from(lit.value())
+            .map_err(|_| syn::Error::new_spanned(lit, "`size_hint` is too large"))?,
+        None => 1024,
+    };
+
+    let (lit, inline) = match (attributes.path, attributes.source) {
+        (Some(_), Some(source)) => {
+            return Err(syn::Error::new_spanned(
+                source,
+                "`path` and `source` can't be used together",
+            ))
+        }
+        (Some(path), None) => (path, false),
+        (None, Some(source)) => (source, true),
+        (None, None) => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "missing `path` or `source` in #[template(...)] attribute",
+            ))
+        }
+    };
 
-    let size_hint: usize = size_hint.and_then(|x| usize::try_from(x).ok()).unwrap_or(1024);
+    let templates_dir = erst_shared::utils::templates_dir()
+        .map_err(|e| syn::Error::new_spanned(&lit, e.to_string()))?;
 
-    let path = path.ok_or_else(|| "No path given")?;
+    let template = if inline {
+        Template::from_source(&templates_dir, &format!("<{} source>", name), &lit.value())
+    } else {
+        Template::resolve(&templates_dir, &lit.value())
+    }
+    .map_err(|e| syn::Error::new_spanned(&lit, e.to_string()))?;
+
+    diagnostic::check(&template).map_err(|e| syn::Error::new_spanned(&lit, e))?;
+
+    // `html` templates pick an escaper for each expression, everything else uses the template's escaper
+    let escapers = if html {
+        Some(context::escapers(&template).map_err(|e| syn::Error::new_spanned(&lit, e))?)
+    } else if escaper.is_some() {
+        let count = template.segments.iter().filter(|x| x.kind == Kind::Expr).count();
+        Some(vec!["__erst_escaper"; count])
+    } else {
+        None
+    };
 
-    let full_path = erst_shared::utils::templates_dir()?.join(&path);
+    // Render errors name each file relative to the templates directory
+    let paths = template
+        .sources
+        .iter()
+        .map(|x| x.path.strip_prefix(&templates_dir).unwrap_or(&x.path).display().to_string())
+        .collect::<Vec<_>>();
 
-    let body = std::fs::read_to_string(&full_path)?;
+    // Inline sources are part of the crate's code, so their text is always compiled in
+    let templates_dir = templates_dir.display().to_string();
+    let dynamic = if inline { None } else { Some((templates_dir.as_str(), lit.value())) };
 
-    let body = parse(&full_path.display().to_string(), &body, type_)?;
+    let pieces = generate(
+        &template,
+        &paths,
+        escapers.as_deref(),
+        dynamic.as_ref().map(|(dir, path)| (*dir, path.as_str())),
+    );
 
-    let body = format!("{{ {} }}", body);
+    let body = format!("{{ {} }}", pieces.concat());
 
-    let block = syn::parse_str::<syn::Block>(&body)?;
+    let block = syn::parse_str::<syn::Block>(&body)
+        .map_err(|e| syn::Error::new_spanned(&lit, diagnostic::locate(&template, &pieces, e)))?;
 
     let stmts = &block.stmts;
 
+    let template_marker = syn::Ident::new(
+        &format!("__ERST_TEMPLATE_MARKER_{}", &name),
+        proc_macro2::Span::call_site(),
+    );
+
     #[cfg(any(not(feature = "dynamic"), not(debug_assertions)))]
-    let template_marker = {
-        let path_display = full_path.display().to_string();
-        let template_marker = syn::Ident::new(
-            &format!("__ERST_TEMPLATE_MARKER_{}", &name),
-            proc_macro2::Span::call_site(),
-        );
-        quote!(pub const #template_marker: () = { include_str!(#path_display); };)
-    };
+    let marker_paths = template
+        .sources
+        .iter()
+        .skip(inline as usize)
+        .map(|x| x.path.display().to_string())
+        .collect::<Vec<_>>();
 
     #[cfg(all(feature = "dynamic", debug_assertions))]
-    let template_marker = {
-        let template_marker = syn::Ident::new(
-            &format!("__ERST_TEMPLATE_MARKER_{}", &name),
-            proc_macro2::Span::call_site(),
-        );
-
-        if let Some(path) = erst_shared::dynamic::get_code_cache_path(&full_path) {
-            let path_display = path.display().to_string();
-            quote!(pub const #template_marker: () = { include_str!(#path_display); };)
-        } else {
-            let path_display = full_path.display().to_string();
-            quote!(pub const #template_marker: () = { include_str!(#path_display); };)
-        }
+    let marker_paths = template
+        .sources
+        .iter()
+        .skip(inline as usize)
+        .map(|x| {
+            erst_shared::dynamic::get_code_cache_path(&x.path)
+                .unwrap_or_else(|| x.path.clone())
+                .display()
+                .to_string()
+        })
+        .collect::<Vec<_>>();
+
+    let template_marker =
+        quote!(pub const #template_marker: () = { #(include_str!(#marker_paths);)* };);
+
+    let (escaper, markup) = match escaper {
+        Some(path) => (
+            match attributes.delimiter {
+                Some(delimiter) => quote! {
+                    #[allow(unused_variables)]
+                    let __erst_escaper = #path::new(#delimiter);
+                },
+                None => quote! {
+                    #[allow(unused_variables)]
+                    let __erst_escaper = <#path as std::default::Default>::default();
+                },
+            },
+            quote!(impl #impl_generics erst::Markup<#path> for #name #ty_generics #where_clause {}),
+        ),
+        None => (quote!(), quote!()),
+    };
+
+    let body = quote! {
+        #template_marker
+        #[allow(unused_imports)]
+        use erst::render::{RenderEscaped as _, RenderJson as _, RenderMarkup as _, RenderStr as _};
+        let __erst_buffer = writer;
+        #escaper
+        #(#stmts)*
+        Ok(())
+    };
+
+    // Templates with an `error` type implement `TryTemplate`, and `Template` on top of it
+    let (render, try_template) = match error {
+        Some(error) => (
+            quote! {
+                fn render_into(&self, writer: &mut dyn std::fmt::Write) -> std::fmt::Result {
+                    erst::TryTemplate::try_render_into(self, writer).map_err(|_| std::fmt::Error)
+                }
+
+                fn render_traced(
+                    &self,
+                    writer: &mut dyn std::fmt::Write,
+                    location: &mut erst::render::Location,
+                ) -> std::fmt::Result {
+                    erst::TryTemplate::try_render_traced(self, writer, location).map_err(|_| std::fmt::Error)
+                }
+            },
+            quote! {
+                impl #impl_generics erst::TryTemplate for #name #ty_generics #where_clause {
+                    type Error = #error;
+
+                    fn try_render_into(&self, writer: &mut dyn std::fmt::Write) -> std::result::Result<(), #error> {
+                        erst::TryTemplate::try_render_traced(self, writer, &mut std::default::Default::default())
+                    }
+
+                    #[allow(unused_variables)]
+                    fn try_render_traced(
+                        &self,
+                        writer: &mut dyn std::fmt::Write,
+                        __erst_location: &mut erst::render::Location,
+                    ) -> std::result::Result<(), #error> {
+                        #body
+                    }
+                }
+            },
+        ),
+        None => (
+            quote! {
+                fn render_into(&self, writer: &mut dyn std::fmt::Write) -> std::fmt::Result {
+                    erst::Template::render_traced(self, writer, &mut std::default::Default::default())
+                }
+
+                #[allow(unused_variables)]
+                fn render_traced(
+                    &self,
+                    writer: &mut dyn std::fmt::Write,
+                    __erst_location: &mut erst::render::Location,
+                ) -> std::fmt::Result {
+                    #body
+                }
+            },
+            quote!(),
+        ),
     };
 
     let out = quote! {
 
         impl #impl_generics erst::Template for #name #ty_generics #where_clause {
-            fn render_into(&self, writer: &mut dyn std::fmt::Write) -> std::fmt::Result {
-                #template_marker
-                let __erst_buffer = writer;
-                #(#stmts)*
-                Ok(())
-            }
+            #render
 
-            fn size_hint() -> usize { #size_hint }
+            fn size_hint(&self) -> usize { #size_hint }
         }
 
+        #try_template
+
         impl #impl_generics std::fmt::Display for #name #ty_generics #where_clause {
             fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
                 erst::Template::render_into(self, f)
             }
         }
+
+        #markup
     };
 
     Ok(out.into())
 }
 
-#[cfg(any(not(feature = "dynamic"), not(debug_assertions)))]
-fn parse(_: &str, template: &str, type_: &str) -> Result<String, Box<dyn std::error::Error>> {
-    use erst_shared::{
-        exp::Parser as _,
-        parser::{ErstParser, Rule},
-    };
-
-    let pairs = ErstParser::parse(erst_shared::parser::Rule::template, template)?;
-
-    let mut buffer = String::new();
-
-    for pair in pairs {
-        match pair.as_rule() {
-            Rule::code => {
-                let inner = pair.into_inner();
-                buffer.push_str(inner.as_str());
+/// Generates the Rust code for each segment of the template
+///
+/// Text segments are numbered separately from code, matching the indices used by `erst::dynamic::get`. `dynamic` is
+/// the templates directory and path to read text from at runtime, if any. `escapers` holds the escaper for each
+/// expression, if the template is escaped. Text and expressions first record their file (named by `paths`) and line,
+/// for `erst::RenderError`.
+fn generate(
+    template: &Template,
+    paths: &[String],
+    escapers: Option<&[&str]>,
+    dynamic: Option<(&str, &str)>,
+) -> Vec<String> {
+    let mut text_idx = 0;
+    let mut escapers = escapers.map(|x| x.iter());
+
+    template
+        .segments
+        .iter()
+        .map(|segment| match segment.kind {
+            Kind::Code => format!("{}\n", segment.content),
+            Kind::Expr => {
+                let write = match escapers.as_mut().and_then(|x| x.next()) {
+                    Some(&context::JS_VALUE) => format!(
+                        "(&&erst::render::JsonValue(&({}))).render_value(__erst_buffer)?;",
+                        segment.content
+                    ),
+                    Some(escaper) => format!(
+                        "(&&&erst::render::Value(&({}), &{})).render_value(__erst_buffer)?;",
+                        segment.content, escaper
+                    ),
+                    None => format!("write!(__erst_buffer, \"{{}}\", {})?;", segment.content),
+                };
+                let offset = diagnostic::start_of(segment);
+                format!("{}{}", location(template, paths, segment.file, offset), write)
             }
-            Rule::expr => match type_ {
-                "html" => {
-                    buffer.push_str(&format!(
-                        "write!(__erst_buffer, \"{{}}\", erst::Html({}))?;",
-                        pair.into_inner().as_str()
-                    ));
-                }
-                _ => {
-                    buffer.push_str(&format!(
-                        "write!(__erst_buffer, \"{{}}\", {})?;",
-                        pair.into_inner().as_str()
-                    ));
-                }
-            },
-            Rule::text => {
-                buffer.push_str(&format!(
-                    "__erst_buffer.write_str(r####\"{}\"####)?;",
-                    pair.as_str()
-                ));
+            Kind::RawExpr => format!(
+                "{}write!(__erst_buffer, \"{{}}\", {})?;",
+                location(template, paths, segment.file, diagnostic::start_of(segment)),
+                segment.content
+            ),
+            Kind::Text => {
+                text_idx += 1;
+                format!(
+                    "{}{}",
+                    location(template, paths, segment.file, segment.offset),
+                    text(dynamic, text_idx - 1, &segment.content)
+                )
             }
-            _ => {}
-        }
-    }
-
-    Ok(buffer)
+            Kind::Directive => String::new(),
+        })
+        .collect()
 }
 
-#[cfg(all(feature = "dynamic", debug_assertions))]
-fn parse(path: &str, template: &str, type_: &str) -> Result<String, Box<dyn std::error::Error>> {
-    use erst_shared::{
-        exp::Parser as _,
-        parser::{ErstParser, Rule},
-    };
+/// Records the line of `offset` in the template file `file`, before writing what's there
+fn location(template: &Template, paths: &[String], file: usize, offset: usize) -> String {
+    let (line, _) = erst_shared::parser::line_col(&template.sources[file].text, offset);
+    format!("__erst_location.set({:?}, {});", paths[file], line)
+}
 
-    let pairs = ErstParser::parse(erst_shared::parser::Rule::template, template)?;
+fn static_text(content: &str) -> String {
+    format!("__erst_buffer.write_str(r####\"{}\"####)?;", content)
+}
 
-    let mut buffer = String::new();
+#[cfg(any(not(feature = "dynamic"), not(debug_assertions)))]
+fn text(_: Option<(&str, &str)>, _: usize, content: &str) -> String {
+    static_text(content)
+}
 
-    for (idx, pair) in pairs.enumerate() {
-        match pair.as_rule() {
-            Rule::code => {
-                let inner = pair.into_inner();
-                buffer.push_str(inner.as_str());
-            }
-            Rule::expr => match type_ {
-                "html" => {
-                    buffer.push_str(&format!(
-                        "write!(__erst_buffer, \"{{}}\", erst::Html({}))?;",
-                        pair.into_inner().as_str()
-                    ));
-                }
-                _ => {
-                    buffer.push_str(&format!(
-                        "write!(__erst_buffer, \"{{}}\", {})?;",
-                        pair.into_inner().as_str()
-                    ));
-                }
-            },
-            Rule::text => {
-                buffer.push_str(&format!(
-                    "write!(__erst_buffer, \"{{}}\", 
-                    erst::dynamic::get(\"{}\", {}).unwrap_or_default())?;",
-                    path, idx
-                ));
-            }
-            _ => {}
-        }
+#[cfg(all(feature = "dynamic", debug_assertions))]
+fn text(dynamic: Option<(&str, &str)>, idx: usize, content: &str) -> String {
+    match dynamic {
+        Some((templates_dir, path)) => format!(
+            "write!(__erst_buffer, \"{{}}\", 
+            erst::dynamic::get({:?}, {:?}, {}).unwrap_or_default())?;",
+            templates_dir, path, idx
+        ),
+        None => static_text(content),
     }
-
-    Ok(buffer)
 }
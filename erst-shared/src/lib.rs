@@ -34,9 +34,499 @@ pub mod err {
 }
 
 pub mod parser {
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    };
+
     #[derive(Parser)]
     #[grammar = "erst.pest"]
     pub struct ErstParser;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Kind {
+        Text,
+        Code,
+        Expr,
+        RawExpr,
+        Directive,
+    }
+
+    /// A piece of a parsed template, with the byte offset of its content in the template source
+    ///
+    /// Comments are dropped when parsing, so they never produce a segment. `file` is the index of the
+    /// template source in [Template::sources](struct.Template.html) (always `0` for a single file).
+    #[derive(Debug, Clone)]
+    pub struct Segment {
+        pub kind: Kind,
+        pub content: String,
+        pub offset: usize,
+        pub file: usize,
+    }
+
+    pub fn segments(template: &str) -> crate::err::Result<Vec<Segment>> {
+        segments_with_path(template, None)
+    }
+
+    pub fn segments_with_path(
+        template: &str,
+        path: Option<&str>,
+    ) -> crate::err::Result<Vec<Segment>> {
+        use pest::Parser as _;
+
+        let pairs = ErstParser::parse(Rule::template, template).map_err(|e| {
+            let e = e.renamed_rules(|rule| match rule {
+                Rule::EOI => "end of template".into(),
+                Rule::code => "`<% ... %>`".into(),
+                Rule::expr => "`<%= ... %>`".into(),
+                Rule::raw_expr => "`<%== ... %>`".into(),
+                Rule::comment => "`<%# ... %>`".into(),
+                Rule::directive => "`<%! ... %>`".into(),
+                other => format!("{:?}", other),
+            });
+            match path {
+                Some(path) => crate::err::Error::Parse(e.with_path(path).to_string()),
+                None => crate::err::Error::Parse(e.to_string()),
+            }
+        })?;
+
+        let mut out: Vec<Segment> = Vec::new();
+
+        for pair in pairs {
+            let kind = match pair.as_rule() {
+                Rule::text => Kind::Text,
+                Rule::code => Kind::Code,
+                Rule::expr => Kind::Expr,
+                Rule::raw_expr => Kind::RawExpr,
+                Rule::directive => Kind::Directive,
+                _ => continue,
+            };
+
+            let inner = match kind {
+                Kind::Text => pair,
+                _ => match pair.into_inner().next() {
+                    Some(inner) => inner,
+                    None => continue,
+                },
+            };
+
+            let content = unescape(kind, inner.as_str());
+
+            // Text on either side of a comment is kept as one segment, so adding or removing a comment
+            // doesn't renumber the text segments read by `erst::dynamic`
+            if kind == Kind::Text {
+                if let Some(last) = out.last_mut().filter(|x| x.kind == Kind::Text) {
+                    last.content.push_str(&content);
+                    continue;
+                }
+            }
+
+            out.push(Segment { kind, content, offset: inner.as_span().start(), file: 0 });
+        }
+
+        Ok(out)
+    }
+
+    /// Replaces the `<%%` and `%%>` escapes with the literal delimiters
+    fn unescape(kind: Kind, content: &str) -> String {
+        match kind {
+            Kind::Text => content.replace("<%%", "<%").replace("%%>", "%>"),
+            _ => content.replace("%%>", "%>"),
+        }
+    }
+
+    /// Returns the 1-based line and column of `offset` in `source`
+    pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+        let before = &source[..offset.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let col =
+            before.rfind('\n').map(|idx| &before[idx + 1..]).unwrap_or(before).chars().count() + 1;
+        (line, col)
+    }
+
+    /// Formats `msg` with the location of `offset` in `source` and a snippet of the offending line
+    pub fn annotate(path: &str, source: &str, offset: usize, msg: &str) -> String {
+        let (line, col) = line_col(source, offset);
+        let snippet = source.lines().nth(line - 1).unwrap_or("");
+        let gutter = " ".repeat(line.to_string().len());
+
+        format!(
+            "{msg}\n{gutter}--> {path}:{line}:{col}\n{gutter} |\n{line} | {snippet}\n{gutter} | {pad}^",
+            msg = msg,
+            gutter = gutter,
+            path = path,
+            line = line,
+            col = col,
+            snippet = snippet,
+            pad = " ".repeat(col - 1),
+        )
+    }
+
+    /// A template file read while resolving a [Template](struct.Template.html)
+    #[derive(Debug, Clone)]
+    pub struct Source {
+        pub path: PathBuf,
+        pub text: String,
+    }
+
+    /// The segments of a template, with any `extends`, `block` and `include` directives resolved
+    #[derive(Debug, Clone)]
+    pub struct Template {
+        pub sources: Vec<Source>,
+        pub segments: Vec<Segment>,
+    }
+
+    impl Template {
+        /// Reads the template at `path` (relative to `templates_dir`) along with any templates it extends or includes
+        pub fn resolve(templates_dir: &Path, path: &str) -> crate::err::Result<Self> {
+            let mut resolver = Resolver { templates_dir, sources: Vec::new(), levels: Vec::new() };
+            let file = resolver.read(path)?;
+            resolver.finish(file)
+        }
+
+        /// Parses `text` as a template named `name`, resolving any templates it extends or includes from
+        /// `templates_dir`
+        ///
+        /// The inline source is always `sources[0]`; `name` is only used in error messages.
+        pub fn from_source(
+            templates_dir: &Path,
+            name: &str,
+            text: &str,
+        ) -> crate::err::Result<Self> {
+            let mut resolver = Resolver { templates_dir, sources: Vec::new(), levels: Vec::new() };
+            resolver.sources.push(Source { path: PathBuf::from(name), text: text.to_string() });
+            resolver.finish(0)
+        }
+
+        /// Formats `msg` with the location of `segment`
+        pub fn annotate(&self, segment: &Segment, offset: usize, msg: &str) -> String {
+            let source = &self.sources[segment.file];
+            annotate(&source.path.display().to_string(), &source.text, offset, msg)
+        }
+    }
+
+    /// Parses the quoted path out of a directive like `extends "layout.erst"`
+    fn parse_path<'a>(directive: &'a str, keyword: &str) -> Option<&'a str> {
+        let rest = directive.trim().trim_start_matches(keyword);
+        if rest.len() == directive.trim().len() {
+            return None;
+        }
+        let rest = rest.trim();
+        if rest.len() >= 2 && rest.starts_with('"') && rest.ends_with('"') {
+            Some(&rest[1..rest.len() - 1])
+        } else {
+            None
+        }
+    }
+
+    enum Node {
+        Segment(Segment),
+        Block(String),
+        Super(Segment),
+    }
+
+    /// The nodes and block definitions of one template in an `extends` chain
+    struct Level {
+        file: usize,
+        nodes: Vec<Node>,
+        blocks: HashMap<String, Block>,
+    }
+
+    /// A block definition
+    struct Block {
+        /// The `block` directive
+        start: Segment,
+        /// Whether it's inside another block of the same template
+        nested: bool,
+        body: Vec<Node>,
+    }
+
+    /// A block being parsed: its name, its opening directive and its body so far
+    type Frame = (String, Segment, Vec<Node>);
+
+    struct Resolver<'a> {
+        templates_dir: &'a Path,
+        sources: Vec<Source>,
+        levels: Vec<Level>,
+    }
+
+    impl Resolver<'_> {
+        /// Loads `file` and every template it extends, then flattens their blocks into a template
+        fn finish(mut self, file: usize) -> crate::err::Result<Template> {
+            let mut next = file;
+
+            while let Some(extends) = self.load(next)? {
+                let path = parse_path(&extends.content, "extends")
+                    .ok_or_else(|| self.error(&extends, "expected `extends \"path\"`"))?;
+
+                next = self.read(path).map_err(|e| self.error(&extends, e))?;
+
+                if self.levels.iter().any(|x| x.file == next) {
+                    return Err(self.error(&extends, "template extends itself"));
+                }
+            }
+
+            let root = self.levels.len() - 1;
+
+            // A block that no parent template has would never be written, e.g. because of a typo in its name
+            for (idx, level) in self.levels[..root].iter().enumerate() {
+                let unknown = level
+                    .blocks
+                    .iter()
+                    .filter(|(name, block)| {
+                        !block.nested
+                            && !self.levels[idx + 1..].iter().any(|x| x.blocks.contains_key(*name))
+                    })
+                    .min_by_key(|(_, block)| (block.start.file, block.start.offset));
+
+                if let Some((name, block)) = unknown {
+                    return Err(self.error(
+                        &block.start,
+                        format!("block `{}` is not in any template this one extends", name),
+                    ));
+                }
+            }
+
+            let mut segments = Vec::new();
+            self.flatten(&self.levels[root].nodes, None, &mut segments)?;
+
+            Ok(Template { sources: self.sources, segments })
+        }
+
+        /// Reads the template at `path`, returning the index of its source
+        fn read(&mut self, path: &str) -> crate::err::Result<usize> {
+            let full_path = self.templates_dir.join(path);
+            if let Some(idx) = self.sources.iter().position(|x| x.path == full_path) {
+                return Ok(idx);
+            }
+            let text = std::fs::read_to_string(&full_path).map_err(|e| {
+                let dir = self.templates_dir.display();
+                // The OS's wording for a missing file differs between platforms
+                crate::err::Error::msg(match e.kind() {
+                    std::io::ErrorKind::NotFound => {
+                        format!("template `{}` not found in `{}`", path, dir)
+                    }
+                    _ => format!("unable to read template `{}` in `{}`: {}", path, dir, e),
+                })
+            })?;
+            self.sources.push(Source { path: full_path, text });
+            Ok(self.sources.len() - 1)
+        }
+
+        fn error(&self, segment: &Segment, msg: impl std::fmt::Display) -> crate::err::Error {
+            let source = &self.sources[segment.file];
+            let offset =
+                segment.offset + (segment.content.len() - segment.content.trim_start().len());
+            crate::err::Error::Parse(annotate(
+                &source.path.display().to_string(),
+                &source.text,
+                offset,
+                &msg.to_string(),
+            ))
+        }
+
+        /// Parses the source at `file` into a new level, returning its `extends` directive if any
+        fn load(&mut self, file: usize) -> crate::err::Result<Option<Segment>> {
+            let mut level = Level { file, nodes: Vec::new(), blocks: HashMap::new() };
+            let mut stack = Vec::new();
+
+            let extends = self.parse(file, &mut level, &mut stack, &mut vec![file])?;
+
+            // Only blocks are used from a template that extends another
+            if extends.is_some() {
+                for node in &level.nodes {
+                    if let Node::Segment(ref segment) = node {
+                        if !is_blank(node) {
+                            return Err(self.error(
+                                segment,
+                                "content outside of a block in a template that uses `extends`",
+                            ));
+                        }
+                    }
+                }
+            }
+
+            self.levels.push(level);
+
+            Ok(extends)
+        }
+
+        /// Parses the source at `file` into `level`, inlining any included templates
+        ///
+        /// `includes` holds the chain of files being included, to detect cycles.
+        fn parse(
+            &mut self,
+            file: usize,
+            level: &mut Level,
+            stack: &mut Vec<Frame>,
+            includes: &mut Vec<usize>,
+        ) -> crate::err::Result<Option<Segment>> {
+            let source = &self.sources[file];
+            let path = source.path.display().to_string();
+            let depth = stack.len();
+
+            let mut extends = None;
+
+            for mut segment in segments_with_path(&source.text, Some(&path))? {
+                segment.file = file;
+
+                let node = if segment.kind == Kind::Directive {
+                    let directive = segment.content.trim();
+                    let keyword = directive.split_whitespace().next().unwrap_or("");
+
+                    match keyword {
+                        "extends" => {
+                            let first = extends.is_none()
+                                && stack.is_empty()
+                                && level.nodes.iter().all(is_blank);
+                            if !first {
+                                return Err(self
+                                    .error(&segment, "`extends` must come first in the template"));
+                            }
+                            extends = Some(segment);
+                            continue;
+                        }
+                        "include" => {
+                            let path = parse_path(directive, "include").ok_or_else(|| {
+                                self.error(&segment, "expected `include \"path\"`")
+                            })?;
+                            let included = self.read(path).map_err(|e| self.error(&segment, e))?;
+
+                            if includes.contains(&included) {
+                                let chain = includes
+                                    .iter()
+                                    .chain(Some(&included))
+                                    .map(|x| {
+                                        let path = &self.sources[*x].path;
+                                        let path =
+                                            path.strip_prefix(self.templates_dir).unwrap_or(path);
+                                        path.display().to_string()
+                                    })
+                                    .collect::<Vec<_>>();
+                                return Err(self.error(
+                                    &segment,
+                                    format!("include cycle: {}", chain.join(" -> ")),
+                                ));
+                            }
+
+                            includes.push(included);
+                            if let Some(extends) = self.parse(included, level, stack, includes)? {
+                                return Err(
+                                    self.error(&extends, "`extends` in an included template")
+                                );
+                            }
+                            includes.pop();
+                            continue;
+                        }
+                        "block" => {
+                            let name = directive["block".len()..].trim();
+                            if name.is_empty() || name.contains(char::is_whitespace) {
+                                return Err(self.error(&segment, "expected `block name`"));
+                            }
+                            stack.push((name.into(), segment, Vec::new()));
+                            continue;
+                        }
+                        "endblock" => {
+                            if stack.len() == depth {
+                                return Err(self.error(&segment, "`endblock` without `block`"));
+                            }
+                            let (name, start, body) = stack.pop().expect("block is open");
+                            let end_name = directive["endblock".len()..].trim();
+                            if !end_name.is_empty() && end_name != name {
+                                return Err(self.error(
+                                    &segment,
+                                    format!("expected `endblock` for block `{}`", name),
+                                ));
+                            }
+                            if level.blocks.contains_key(&name) {
+                                return Err(self
+                                    .error(&start, format!("block `{}` is defined twice", name)));
+                            }
+                            let nested = !stack.is_empty();
+                            level.blocks.insert(name.clone(), Block { start, nested, body });
+                            Node::Block(name)
+                        }
+                        "super()" | "super" => {
+                            if stack.is_empty() {
+                                return Err(self.error(&segment, "`super()` outside of a block"));
+                            }
+                            Node::Super(segment)
+                        }
+                        _ => {
+                            return Err(
+                                self.error(&segment, format!("unknown directive `{}`", keyword))
+                            )
+                        }
+                    }
+                } else {
+                    Node::Segment(segment)
+                };
+
+                match stack.last_mut() {
+                    Some((_, _, body)) => body.push(node),
+                    None => level.nodes.push(node),
+                }
+            }
+
+            if stack.len() > depth {
+                let (name, start, _) = stack.pop().expect("block is open");
+                return Err(self.error(&start, format!("block `{}` is never closed", name)));
+            }
+
+            Ok(extends)
+        }
+
+        /// Writes out `nodes`, replacing each block with its most derived definition
+        ///
+        /// `block` is the name and level of the block definition being written, used to find the parent for `super()`.
+        fn flatten(
+            &self,
+            nodes: &[Node],
+            block: Option<(&str, usize)>,
+            out: &mut Vec<Segment>,
+        ) -> crate::err::Result<()> {
+            for node in nodes {
+                match node {
+                    Node::Segment(segment) => match out.last_mut() {
+                        Some(last) if last.kind == Kind::Text && segment.kind == Kind::Text => {
+                            last.content.push_str(&segment.content);
+                        }
+                        _ => out.push(segment.clone()),
+                    },
+                    Node::Block(name) => {
+                        let (level, body) = self.definition(name, 0).expect("block is defined");
+                        self.flatten(body, Some((name, level)), out)?;
+                    }
+                    Node::Super(segment) => {
+                        let (name, level) = block.expect("`super()` is inside a block");
+                        let (level, body) = self.definition(name, level + 1).ok_or_else(|| {
+                            self.error(segment, format!("no parent block `{}` for `super()`", name))
+                        })?;
+                        self.flatten(body, Some((name, level)), out)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Finds the first definition of block `name`, starting from level `from` (`0` is the most derived)
+        fn definition(&self, name: &str, from: usize) -> Option<(usize, &[Node])> {
+            self.levels
+                .iter()
+                .enumerate()
+                .skip(from)
+                .find_map(|(idx, level)| level.blocks.get(name).map(|block| (idx, &block.body[..])))
+        }
+    }
+
+    fn is_blank(node: &Node) -> bool {
+        match node {
+            Node::Segment(segment) => {
+                segment.kind == Kind::Text && segment.content.trim().is_empty()
+            }
+            _ => false,
+        }
+    }
 }
 
 pub mod exp {
@@ -59,6 +549,15 @@ pub mod utils {
     }
 }
 
+#[cfg(feature = "rust")]
+pub mod rust {
+
+    /// Checks that `source` parses as a Rust file
+    pub fn check(source: &str) -> crate::err::Result<()> {
+        syn2::parse_file(source).map(|_| ()).map_err(|e| crate::err::Error::Parse(e.to_string()))
+    }
+}
+
 #[cfg(feature = "dynamic")]
 pub mod dynamic {
 
@@ -72,8 +571,12 @@ pub mod dynamic {
         for path in template_paths() {
             let path_name =
                 path.file_name().ok_or_else(|| crate::err::Error::msg("No file name"))?;
+            // A template that doesn't parse can't be compiled either, and the derive reports a better error for it
+            let template_code = match get_template_code(&path) {
+                Ok(template_code) => template_code,
+                Err(_) => continue,
+            };
             let cache_file_path = xdg_dirs.place_cache_file(path_name)?;
-            let template_code = get_template_code(&path)?;
 
             if let Ok(cache_file_contents) = std::fs::read_to_string(&cache_file_path) {
                 if cache_file_contents == template_code {
@@ -124,28 +627,27 @@ pub mod dynamic {
     }
 
     fn get_template_code(path: impl AsRef<Path>) -> crate::err::Result<String> {
-        use crate::{
-            exp::Parser as _,
-            parser::{ErstParser, Rule},
-        };
+        use crate::parser::Kind;
 
         let template = std::fs::read_to_string(&path)?;
 
         let mut buffer = String::from("{");
 
-        let pairs = ErstParser::parse(Rule::template, &template)
-            .map_err(|e| crate::err::Error::Parse(e.to_string()))?;
-
-        for pair in pairs {
-            match pair.as_rule() {
-                Rule::code => {
-                    buffer.push_str(pair.into_inner().as_str());
+        for segment in crate::parser::segments(&template)? {
+            match segment.kind {
+                Kind::Code => {
+                    buffer.push_str(&segment.content);
+                    buffer.push('\n');
                 }
-                Rule::expr => {
-                    buffer.push_str(pair.into_inner().as_str());
+                Kind::Expr | Kind::RawExpr => {
+                    buffer.push_str(&segment.content);
                     buffer.push_str(";");
                 }
-                _ => {}
+                // Directives change the structure of the template, so they are kept in the cache as string literals
+                Kind::Directive => {
+                    buffer.push_str(&format!("{:?};", segment.content.trim()));
+                }
+                Kind::Text => {}
             }
         }
 
@@ -23,7 +23,7 @@ fn main() {
 
 Where `simple.erst` looks like:
 
-```
+```text
 <div>
     <p>Hello!</p>
     <%
@@ -43,6 +43,21 @@ By default, the template's `path` will resolve to a file inside a `templates` di
 appropriate path. Note that this is only a concern when building; since the templates are compiled into your binary,
 you don't need this structure/environment variables when running a compiled binary.
 
+For short templates, the source can be given inline with `source` instead of `path`:
+
+```rust,no_run
+use erst::Template;
+
+#[derive(Template)]
+#[template(source = "Hello, <%= self.name %>!", type = "html")]
+pub struct Greeting<'a> {
+    pub name: &'a str,
+}
+```
+
+Inline templates can still extend or include files from the templates directory. With the `dynamic` feature, the
+text of an inline template is always compiled in.
+
 Note that, unlike `Askama` and many other template systems, you need to reference any members of your `Template` item
 with `self` inside the template file. The template file is basically the body of a function that takes `&self`
 (where `self` is the linked `Container` object).
@@ -50,11 +65,213 @@ with `self` inside the template file. The template file is basically the body of
 Note that, like Askama and other precompiled template systems, you can reference any item (structs, functions, etc.)
 available in your crate.
 
-Currently, only the `html` type (or none) is supported, with very basic HTML escaping. To unescape HTML content in your
-template file, wrap the content in [Raw](struct.Raw.html), e.g.:
+Template tags:
+
+- `<% code %>`: Rust code, run as part of rendering
+- `<%= expr %>`: an expression, written to the output (escaped according to the template's `type`)
+- `<%== expr %>`: an expression, written to the output without escaping
+- `<%# comment %>`: a comment, removed from both the output and the generated code
+- `<%! directive %>`: a directive, handled when compiling the template (see [Template inheritance](#template-inheritance)
+  and [Includes](#includes))
+
+Ending a tag with `-%>` trims whitespace after it; starting a code tag with `<%-` trims whitespace before it.
+
+To write a literal `<%` in template text, use `<%%`. Similarly, `%%>` is written as `%>`, both in template text and
+inside tags (e.g., in a string in your code).
+
+`Template` can be used as a trait object, e.g., to pick a page at runtime:
+
+```rust,no_run
+# use erst::Template;
+# #[derive(Template)]
+# #[template(path = "simple.erst", type = "html")]
+# pub struct Container<'a> {
+#     pub collection: Vec<&'a str>,
+# }
+let pages: Vec<Box<dyn Template>> = vec![Box::new(Container { collection: vec!["Hello"] })];
+
+for page in &pages {
+    println!("{}", page);
+}
+```
+
+To write a template to a socket, an HTTP body or a file without rendering it to a `String` first, use
+[render_to_io](trait.Template.html#method.render_to_io) or [write_to_file](trait.Template.html#method.write_to_file):
+
+```rust,no_run
+# use erst::Template;
+# #[derive(Template)]
+# #[template(path = "simple.erst", type = "html")]
+# pub struct Container<'a> {
+#     pub collection: Vec<&'a str>,
+# }
+# fn main() -> std::io::Result<()> {
+let page = Container { collection: vec!["Hello"] };
+
+page.render_to_io(&mut std::io::stdout().lock())?;
+page.write_to_file("index.html")?;
+# Ok(())
+# }
+```
+
+For large pages, [into_chunks](trait.Template.html#method.into_chunks) renders on a background thread and yields the
+output in chunks as it's rendered, so the start of the page can be sent before the rest is ready. With the `async`
+feature, [into_stream](trait.Template.html#method.into_stream) does the same as a `futures::Stream`.
+
+# Template inheritance
+
+A template can extend a layout, overriding the layout's named blocks with `<%! extends "path" %>` and
+`<%! block name %>...<%! endblock %>` directives. Inside a block, `<%! super() %>` renders the parent's content for that
+block. Paths are resolved relative to the templates directory, and layouts can themselves extend other layouts.
+
+```text
+<%# layout.erst %>
+<html>
+    <head><title><%! block title %>My site<%! endblock %></title></head>
+    <body><%! block content %><%! endblock %></body>
+</html>
+```
+
+```text
+<%# page.erst %>
+<%! extends "layout.erst" %>
+<%! block title %><%= self.title %> | <%! super() %><%! endblock %>
+<%! block content %><p>Hello!</p><%! endblock %>
+```
+
+Anything outside of a block in a template that uses `extends` must be whitespace, and each of its blocks must override
+one of a layout's (or be nested in one of its own blocks), so a misspelled block name is a compile error.
+
+# Includes
+
+`<%! include "path" %>` inlines another template when compiling, so the included template has access to `self` and
+any local variables where it is included:
+
+```text
+<% for item in &self.items { %><%! include "partials/item.erst" %><% } %>
+```
+
+# Escaping
+
+The `type` of a template picks how `<%= expr %>` output is escaped:
+
+- `html`: HTML escaping, picked from where the expression is in the document (see
+  [HTML contexts](#html-contexts))
+- `xml`: the five XML entities, replacing characters that aren't allowed in XML 1.0; wrap content in
+  [Cdata](struct.Cdata.html) to write it as a CDATA section instead
+- `json`: the contents of a JSON string, safe inside `<script>`; wrap values in [Json](struct.Json.html) to write them
+  as complete JSON literals instead (e.g., `"tags": <%= erst::Json(&self.tags) %>`)
+- `js`: the contents of a JavaScript string, following OWASP's rules; wrap values in [Js](struct.Js.html) to escape
+  them this way in other templates (e.g., inside `<script>` in an `html` template)
+- `css`: CSS escapes, following OWASP's rules; wrap values in [Css](struct.Css.html) to escape them this way in other
+  templates (e.g., inside `<style>` in an `html` template)
+- `url`: percent-encoding for URL components; [UrlComponent](struct.UrlComponent.html),
+  [UrlPath](struct.UrlPath.html) and [Query](struct.Query.html) encode values this way in other templates, and are only
+  escaped once in `html` templates (e.g., `<a href="/search?<%= erst::Query::new().param("q", &self.q) %>">`)
+- `sh`: POSIX shell single-quoted strings, so each expression is one argument; see also
+  [ShellQuote](struct.ShellQuote.html)
+- `sql`: SQL string literals; see also [SqlLiteral](struct.SqlLiteral.html)
+- `rust`: the contents of a Rust string literal, for generating code (e.g., in a build script); see also
+  [RustStr](struct.RustStr.html), [RustChar](struct.RustChar.html) and [RustIdent](struct.RustIdent.html). With the
+  `rust` feature, [check_rust](fn.check_rust.html) checks that the output parses.
+- `latex`: LaTeX's special characters, written so they're printed as is
+- `markdown`: backslash escapes for all ASCII punctuation, so values are always plain text
+- `csv`: RFC 4180 quoting, so each expression is one field; set the delimiter with `delimiter = ';'` (`,` by default)
+- none: no escaping
+
+An unknown `type` is a compile error. To unescape content in your template file, use the `<%== expr %>` tag or wrap the
+content in [Raw](struct.Raw.html), e.g.:
+
+```rust,no_run
+erst::Raw("<p>Hello</p>");
+```
+
+Templates with a `type` implement [Markup](trait.Markup.html) for its escaper, so rendering one inside another of the
+same type (e.g., `<%= self.sidebar %>`) doesn't escape it a second time. Implement `Markup` for your own types if their
+`Display` output is already safely escaped.
+
+## HTML contexts
+
+In `html` templates, the escaper for each `<%= expr %>` is picked from the surrounding template text when compiling:
+
+- element text: HTML escaping, with [Markup](trait.Markup.html) (e.g., a nested template) written as is
+- comments, `<title>`, `<textarea>` and quoted attribute values: [HtmlAttrEscaper](struct.HtmlAttrEscaper.html), which
+  escapes markup too (use `<%== expr %>` to write a value as is)
+- the start of a URL attribute (e.g., `href` or `src`): [HtmlUrlEscaper](struct.HtmlUrlEscaper.html), which also
+  replaces `javascript:` and other unsafe URLs
+- the rest of a URL attribute: [HtmlUrlComponentEscaper](struct.HtmlUrlComponentEscaper.html)
+- strings in `<script>` and in event handler attributes (e.g., `onclick`): [JsEscaper](struct.JsEscaper.html)
+- elsewhere in `<script>`: a JSON literal, so e.g. `var page = <%= self.page %> + 1;` keeps `page` a number (see
+  [JsValueEscaper](struct.JsValueEscaper.html); values that don't implement [ToJson](trait.ToJson.html) are a compile
+  error)
+- `<style>` and `style` attributes: [CssEscaper](struct.CssEscaper.html)
+
+Expressions in unquoted attribute values, outside of attribute values in a tag, or outside of strings in event handler
+attributes are compile errors. Only the template text is used to find each context (not code or `<%== expr %>`
+output), and with the `dynamic` feature, changes to the text around an expression need a rebuild to change its context.
+
+For other output formats, implement [Escaper](trait.Escaper.html) and use `escape` instead of `type`:
+
+```rust,no_run
+use erst::{Escaper, Template};
+use std::fmt::Write;
+
+#[derive(Default)]
+pub struct Shout;
+
+impl Escaper for Shout {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        writer.write_str(&input.to_uppercase())
+    }
+}
+
+#[derive(Template)]
+#[template(source = "Hello, <%= self.name %>!", escape = "Shout")]
+pub struct Greeting<'a> {
+    pub name: &'a str,
+}
+```
+
+# Errors
+
+When rendering fails, [render](trait.Template.html#method.render) returns a [RenderError](struct.RenderError.html),
+which says which template file and line failed, e.g., "error rendering template `tests/page.erst` at line 12". It
+converts into `fmt::Error`, so `render()?` still works in functions returning `fmt::Result`.
+
+Code in a template can only use `?` on `fmt::Error`. To use it on your own errors, give the template an `error` type,
+which must implement `From<std::fmt::Error>`; it then implements [TryTemplate](trait.TryTemplate.html):
 
 ```rust,no_run
-erst::Raw("<p>Hello</p>")
+use erst::{Template, TryTemplate};
+
+#[derive(Debug)]
+pub enum Error {
+    Fmt(std::fmt::Error),
+    Price(std::num::ParseFloatError),
+}
+
+impl From<std::fmt::Error> for Error {
+    fn from(e: std::fmt::Error) -> Self {
+        Error::Fmt(e)
+    }
+}
+
+impl From<std::num::ParseFloatError> for Error {
+    fn from(e: std::num::ParseFloatError) -> Self {
+        Error::Price(e)
+    }
+}
+
+#[derive(Template)]
+#[template(source = "Total: <%= format!(\"{:.2}\", self.price.parse::<f64>()?) %>", type = "html", error = "Error")]
+pub struct Invoice<'a> {
+    pub price: &'a str,
+}
+
+fn main() -> Result<(), Error> {
+    println!("{}", Invoice { price: "12.5" }.try_render()?);
+    Ok(())
+}
 ```
 
 # Dynamic
@@ -73,7 +290,7 @@ erst = { version = "0.2", features = ["dynamic"] }
 
 You must also install a helper binary, [erst-prepare](https://crates.io/crates/erst-prepare):
 
-```
+```text
 cargo install erst-prepare
 ```
 
@@ -82,13 +299,13 @@ so that the build script can re-run on changes only to the code part of your tem
 
 Then run your project like:
 
-```
+```text
 erst-prepare && cargo run
 ```
 
 If you have a unique setup, you may need to use the `--pkg-name` and `--templates-dir` flags to `erst-prepare`:
 
-```
+```text
 erst-prepare --pkg-name my-project --templates-dir /path/to/your/templates/dir
 ```
 
@@ -101,155 +318,1597 @@ pub use erst_derive::Template;
 use std::fmt::{Display, Write};
 
 /// The rendering trait derived by the proc macro
+///
+/// The trait is object safe, so templates can be chosen at runtime or collected as `Box<dyn Template>`.
 pub trait Template {
     fn render_into(&self, writer: &mut dyn std::fmt::Write) -> std::fmt::Result;
 
-    fn size_hint() -> usize;
+    fn size_hint(&self) -> usize;
+
+    /// Like `render_into`, but records the position of each part of the template in `location` before writing it
+    #[doc(hidden)]
+    fn render_traced(
+        &self,
+        writer: &mut dyn std::fmt::Write,
+        _location: &mut render::Location,
+    ) -> std::fmt::Result {
+        self.render_into(writer)
+    }
+
+    /// Renders the template to a `String`
+    ///
+    /// If rendering fails, the error says which template (and line) failed.
+    fn render(&self) -> Result<String, RenderError> {
+        let mut buffer = String::with_capacity(self.size_hint());
+        let mut location = render::Location::default();
+        self.render_traced(&mut buffer, &mut location).map_err(|e| location.error(e))?;
+        Ok(buffer)
+    }
+
+    /// Renders the template straight to `writer` (e.g., a socket or an HTTP body), without building a `String` first
+    ///
+    /// The output is buffered, and `writer` is flushed at the end. An error from `writer` is returned as is; if the
+    /// template itself fails (e.g., a value's `Display` implementation returns an error), this returns an error of kind
+    /// `Other` wrapping a [RenderError](struct.RenderError.html).
+    ///
+    /// If the template fails, output still in the buffer is dropped instead of written, but anything past the buffer's
+    /// first 8 KiB may already have reached `writer`. Render to a `String` first if `writer` must not see partial
+    /// output.
+    fn render_to_io(&self, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+        use std::io::Write;
+
+        let mut buffer = std::io::BufWriter::new(writer);
+        let mut adapter = IoWriter { writer: &mut buffer, error: None };
+        let mut location = render::Location::default();
+
+        if let Err(e) = self.render_traced(&mut adapter, &mut location) {
+            let error = adapter.error.unwrap_or_else(|| std::io::Error::other(location.error(e)));
+            // Dropping a `BufWriter` flushes it
+            let _ = buffer.into_parts();
+            return Err(error);
+        }
+
+        buffer.flush()
+    }
+
+    /// Renders the template to the file at `path`, creating it or replacing it
+    ///
+    /// The template is rendered to a temporary file next to `path`, which is renamed to `path` once rendering succeeds,
+    /// so a failed render leaves any existing file untouched.
+    fn write_to_file<P>(&self, path: P) -> std::io::Result<()>
+    where
+        P: AsRef<std::path::Path>,
+        Self: Sized,
+    {
+        let path = path.as_ref();
+        let file_name = path.file_name().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name")
+        })?;
+
+        // Unique per call, so concurrent writes to the same path each render to their own file
+        static TEMP_FILES: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let count = TEMP_FILES.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(".{}.{}.tmp", std::process::id(), count));
+        let temp_path = path.with_file_name(temp_name);
+
+        let mut file = std::fs::OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+        let result = self.render_to_io(&mut file);
+        drop(file);
+        let result = result.and_then(|()| std::fs::rename(&temp_path, path));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    /// Renders the template in chunks of at most `chunk_size` bytes, passing each to `on_chunk` as soon as it's full
+    ///
+    /// Every chunk holds at least one character, so a `chunk_size` below 4 may be exceeded. Rendering stops at the first
+    /// error from `on_chunk`.
+    fn render_chunks(
+        &self,
+        chunk_size: usize,
+        on_chunk: &mut dyn FnMut(String) -> std::fmt::Result,
+    ) -> std::fmt::Result {
+        let mut writer =
+            ChunkWriter { chunk: String::with_capacity(chunk_size), chunk_size, on_chunk };
+        self.render_into(&mut writer)?;
+        writer.finish()
+    }
+
+    /// Renders the template on a background thread, returning an iterator over chunks of at most `chunk_size` bytes
+    ///
+    /// Each chunk is yielded as soon as it's rendered, so e.g. an HTTP server can send a page's `<head>` before the rest
+    /// is ready. Rendering stays at most one chunk ahead of the iterator, and stops if the iterator is dropped. If the
+    /// template fails or panics, the last item is an error.
+    ///
+    /// Each call spawns an OS thread that lives until rendering ends, so this suits a few large pages better than many
+    /// small ones. The template is moved to that thread, hence `Send + 'static`: templates that borrow their data (e.g.,
+    /// `Container<'a>`) can't use this, so own the data or use [render_chunks](#method.render_chunks) instead.
+    fn into_chunks(self, chunk_size: usize) -> Chunks
+    where
+        Self: Sized + Send + 'static,
+    {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        spawn_chunks(self, chunk_size, move |item| sender.send(item).is_ok());
+        Chunks(receiver)
+    }
+
+    /// Like [into_chunks](#method.into_chunks), but returns a `futures::Stream`
+    ///
+    /// Rendering still happens on a background thread (one per call, with the same `Send + 'static` bound), so this
+    /// works with any executor.
+    #[cfg(feature = "async")]
+    fn into_stream(self, chunk_size: usize) -> ChunkStream
+    where
+        Self: Sized + Send + 'static,
+    {
+        use futures::SinkExt;
+
+        let (mut sender, receiver) = futures::channel::mpsc::channel(0);
+        spawn_chunks(self, chunk_size, move |item| {
+            futures::executor::block_on(sender.send(item)).is_ok()
+        });
+        ChunkStream(receiver)
+    }
+}
+
+/// A template whose code can fail with its own error type, derived with `#[template(error = "path")]`
+///
+/// `?` works in the template's code on any error that converts into `Error`. Such templates also implement `Template`
+/// (and `Display`), but an error is then reported as `fmt::Error`.
+pub trait TryTemplate: Template {
+    type Error: From<std::fmt::Error>;
+
+    fn try_render_into(&self, writer: &mut dyn std::fmt::Write) -> Result<(), Self::Error>;
+
+    /// Like `try_render_into`, but records the position of each part of the template in `location` before writing it
+    #[doc(hidden)]
+    fn try_render_traced(
+        &self,
+        writer: &mut dyn std::fmt::Write,
+        _location: &mut render::Location,
+    ) -> Result<(), Self::Error> {
+        self.try_render_into(writer)
+    }
 
-    fn render(&self) -> Result<String, std::fmt::Error> {
-        let mut buffer = String::with_capacity(Self::size_hint());
-        self.render_into(&mut buffer)?;
+    fn try_render(&self) -> Result<String, Self::Error> {
+        let mut buffer = String::with_capacity(self.size_hint());
+        self.try_render_into(&mut buffer)?;
         Ok(buffer)
     }
 }
 
-#[doc(hidden)]
-pub struct Html<T>(pub T);
+/// Renders `template` on a new thread, passing each chunk to `send` until it returns `false`
+fn spawn_chunks<T, F>(template: T, chunk_size: usize, mut send: F)
+where
+    T: Template + Send + 'static,
+    F: FnMut(Result<String, std::fmt::Error>) -> bool + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            template.render_chunks(chunk_size, &mut |chunk| {
+                if send(Ok(chunk)) {
+                    Ok(())
+                } else {
+                    Err(std::fmt::Error)
+                }
+            })
+        }));
 
-/// Wrap any `Display` content in this tuple struct to unescape any included HTML
-pub struct Raw<T>(pub T);
+        // Without an error, a panic would look like the end of the output
+        if let Err(e) = result.unwrap_or(Err(std::fmt::Error)) {
+            send(Err(e));
+        }
+    });
+}
+
+/// Collects output into chunks for `render_chunks`
+struct ChunkWriter<'a> {
+    chunk: String,
+    chunk_size: usize,
+    on_chunk: &'a mut dyn FnMut(String) -> std::fmt::Result,
+}
 
-struct HtmlWriter<'a, 'b: 'a>(&'a mut std::fmt::Formatter<'b>);
+impl ChunkWriter<'_> {
+    fn emit(&mut self) -> std::fmt::Result {
+        let chunk = std::mem::replace(&mut self.chunk, String::with_capacity(self.chunk_size));
+        (self.on_chunk)(chunk)
+    }
 
-impl HtmlWriter<'_, '_> {
-    fn write_slice(&mut self, bytes: &[u8]) -> std::fmt::Result {
-        let bstr = erst_shared::exp::B(bytes);
-        if let Ok(s) = bstr.to_str() {
-            self.0.write_str(s)?;
+    fn finish(mut self) -> std::fmt::Result {
+        if self.chunk.is_empty() {
+            Ok(())
         } else {
-            for chr in bstr.chars() {
-                self.0.write_char(chr)?;
+            self.emit()
+        }
+    }
+}
+
+impl Write for ChunkWriter<'_> {
+    fn write_str(&mut self, mut s: &str) -> std::fmt::Result {
+        while !s.is_empty() {
+            let mut at = self.chunk_size.saturating_sub(self.chunk.len()).min(s.len());
+            while !s.is_char_boundary(at) {
+                at -= 1;
+            }
+            if at == 0 && self.chunk.is_empty() {
+                at = s.chars().next().map_or(0, char::len_utf8);
+            }
+
+            self.chunk.push_str(&s[..at]);
+            s = &s[at..];
+
+            if !s.is_empty() || self.chunk.len() >= self.chunk_size {
+                self.emit()?;
             }
         }
+
         Ok(())
     }
 }
 
-impl std::io::Write for HtmlWriter<'_, '_> {
-    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
-        use std::io::{Error, ErrorKind};
+/// The chunks of a template rendered on a background thread; see
+/// [Template::into_chunks](trait.Template.html#method.into_chunks)
+pub struct Chunks(std::sync::mpsc::Receiver<Result<String, std::fmt::Error>>);
 
-        let mut from = 0;
+impl Iterator for Chunks {
+    type Item = Result<String, std::fmt::Error>;
 
-        for (idx, byte) in bytes.into_iter().enumerate() {
-            if byte < &b'"' || byte > &b'>' {
-                continue;
-            }
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.recv().ok()
+    }
+}
 
-            let rep_opt = match byte {
-                b'<' => Some("&lt;"),
-                b'>' => Some("&gt;"),
-                b'&' => Some("&amp;"),
-                b'"' => Some("&quot;"),
-                b'\'' => Some("&#x27;"),
-                b'/' => Some("&#x2f;"),
-                _ => None,
-            };
+/// The chunks of a template rendered on a background thread, as a `futures::Stream`; see
+/// [Template::into_stream](trait.Template.html#method.into_stream)
+#[cfg(feature = "async")]
+pub struct ChunkStream(futures::channel::mpsc::Receiver<Result<String, std::fmt::Error>>);
 
-            if let Some(rep) = rep_opt {
-                self.write_slice(&bytes[from..idx]).map_err(|e| Error::new(ErrorKind::Other, e))?;
-                self.0.write_str(rep).map_err(|e| Error::new(ErrorKind::Other, e))?;
-                from = idx + 1;
-            }
+#[cfg(feature = "async")]
+impl futures::Stream for ChunkStream {
+    type Item = Result<String, std::fmt::Error>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        std::pin::Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+/// The error returned when a template fails to render: where it failed, and the underlying `fmt::Error`
+///
+/// The location is the template file (relative to the templates directory, or `<Name source>` for inline templates) and
+/// line of the text or `<%= expr %>` being written. It's unknown for templates that don't derive `Template`. Failures
+/// in a nested template are reported at the expression that writes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderError {
+    location: Option<(&'static str, usize)>,
+    cause: std::fmt::Error,
+}
+
+impl RenderError {
+    /// The path of the template that failed, if known
+    pub fn path(&self) -> Option<&str> {
+        self.location.map(|x| x.0)
+    }
+
+    /// The 1-based line in the template that failed, if known
+    pub fn line(&self) -> Option<usize> {
+        self.location.map(|x| x.1)
+    }
+}
+
+impl Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.location {
+            Some((path, line)) => write!(f, "error rendering template `{}` at line {}", path, line),
+            None => f.write_str("error rendering template"),
         }
+    }
+}
+
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.cause)
+    }
+}
 
-        let bytes_len = bytes.len();
+impl From<RenderError> for std::fmt::Error {
+    fn from(e: RenderError) -> Self {
+        e.cause
+    }
+}
 
-        self.write_slice(&bytes[from..bytes_len]).map_err(|e| Error::new(ErrorKind::Other, e))?;
+/// Writes to an `io::Write`, keeping its error so `render_to_io` can return it instead of `fmt::Error`
+struct IoWriter<'a> {
+    writer: &'a mut dyn std::io::Write,
+    error: Option<std::io::Error>,
+}
 
-        Ok(bytes_len)
+impl Write for IoWriter<'_> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.writer.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            std::fmt::Error
+        })
     }
+}
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        Ok(())
+macro_rules! impl_template_for_pointer {
+    ($($ty:ty),*) => {
+        $(
+            impl<T> Template for $ty
+            where
+                T: Template + ?Sized,
+            {
+                fn render_into(&self, writer: &mut dyn std::fmt::Write) -> std::fmt::Result {
+                    (**self).render_into(writer)
+                }
+
+                fn size_hint(&self) -> usize {
+                    (**self).size_hint()
+                }
+
+                fn render_traced(
+                    &self,
+                    writer: &mut dyn std::fmt::Write,
+                    location: &mut render::Location,
+                ) -> std::fmt::Result {
+                    (**self).render_traced(writer, location)
+                }
+            }
+        )*
+    };
+}
+
+impl_template_for_pointer!(&T, Box<T>, std::rc::Rc<T>, std::sync::Arc<T>);
+
+impl Display for dyn Template + '_ {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.render_into(f)
+    }
+}
+
+/// Escapes the output of `<%= expr %>` tags
+///
+/// Templates with `type = "html"` use [HtmlEscaper](struct.HtmlEscaper.html); use `#[template(escape = "path")]` to
+/// plug in your own. The escaper is created with `Default::default()` each time the template is rendered (except for
+/// `type = "csv"` with a `delimiter`, which uses [CsvEscaper::new](struct.CsvEscaper.html#method.new)).
+pub trait Escaper {
+    /// Writes `input` to `writer`, escaped
+    ///
+    /// This may be called several times for one value, with whatever chunks its `Display` implementation writes.
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result;
+
+    /// Writes `value` to `writer`, escaped
+    ///
+    /// By default, this passes each chunk of the value's `Display` output to [escape](#tymethod.escape). Override it if
+    /// the escaper needs to see the whole value at once (e.g., to quote it).
+    fn write_value(&self, value: &dyn Display, writer: &mut dyn Write) -> std::fmt::Result {
+        write!(EscapeWriter { escaper: self, writer }, "{}", value)
+    }
+
+    /// Writes the string `value` to `writer`, escaped
+    ///
+    /// Templates call this instead of [write_value](#method.write_value) for `str`, `String` and other string types.
+    /// By default, it calls `write_value`; override it to escape strings without going through `Display`.
+    fn write_str(&self, value: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        self.write_value(&value, writer)
+    }
+
+    /// Writes `value`, which is already escaped for this escaper (it implements [Markup](trait.Markup.html)), to
+    /// `writer`
+    ///
+    /// By default, this writes the value as is. Override it if the escaper's output is nested in another format that
+    /// still needs escaping (e.g., a URL in an HTML attribute).
+    fn write_markup(&self, value: &dyn Display, writer: &mut dyn Write) -> std::fmt::Result {
+        write!(writer, "{}", value)
     }
 }
 
-impl<T> Display for Html<T>
+/// Passes everything written to it through an escaper
+struct EscapeWriter<'a, E: ?Sized> {
+    escaper: &'a E,
+    writer: &'a mut dyn Write,
+}
+
+impl<E> Write for EscapeWriter<'_, E>
 where
-    T: Display,
+    E: Escaper + ?Sized,
 {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        use std::io::Write;
-        write!(HtmlWriter(f), "{}", &self.0).map_err(|_| std::fmt::Error)?;
-        Ok(())
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.escaper.escape(s, self.writer)
+    }
+}
+
+/// The escaper for `type = "html"`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HtmlEscaper;
+
+impl Escaper for HtmlEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        escape_html(input, writer)
+    }
+
+    fn write_value(&self, value: &dyn Display, writer: &mut dyn Write) -> std::fmt::Result {
+        write!(HtmlWriter(writer), "{}", value)
+    }
+
+    fn write_str(&self, value: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        escape_html(value, writer)
+    }
+}
+
+/// The escaper for quoted attribute values, comments and the text of `<title>` and `<textarea>` in `type = "html"`
+/// templates
+///
+/// Escapes like [HtmlEscaper](struct.HtmlEscaper.html), but also escapes [Markup](trait.Markup.html) values (including
+/// [Raw](struct.Raw.html) and nested templates), since markup can't appear in these places: e.g., the `"` in a nested
+/// template's `class="..."` would end the attribute.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HtmlAttrEscaper;
+
+impl Escaper for HtmlAttrEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        escape_html(input, writer)
+    }
+
+    fn write_value(&self, value: &dyn Display, writer: &mut dyn Write) -> std::fmt::Result {
+        write!(HtmlWriter(writer), "{}", value)
+    }
+
+    fn write_str(&self, value: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        escape_html(value, writer)
+    }
+
+    fn write_markup(&self, value: &dyn Display, writer: &mut dyn Write) -> std::fmt::Result {
+        self.write_value(value, writer)
     }
 }
 
-impl<T> Display for Html<Raw<T>>
+/// A marker for values whose `Display` output is already escaped with the escaper `E`
+///
+/// Every `#[derive(Template)]` with a `type` (or `escape`) implements this for its escaper, so rendering one template
+/// inside another of the same type (e.g., `<%= self.sidebar %>`) writes the inner template's output as is, while
+/// plain strings are still escaped.
+pub trait Markup<E = HtmlEscaper>: Display {}
+
+impl<T, E> Markup<E> for &T where T: Markup<E> + ?Sized {}
+
+impl<T, E> Markup<E> for Box<T> where T: Markup<E> + ?Sized {}
+
+impl<T, E> Markup<E> for std::rc::Rc<T> where T: Markup<E> + ?Sized {}
+
+impl<T, E> Markup<E> for std::sync::Arc<T> where T: Markup<E> + ?Sized {}
+
+impl<T, E> Markup<E> for Raw<T> where T: Display {}
+
+/// Wrap any `Display` content in this tuple struct to write it without escaping, whatever the template's type
+///
+/// In `html` templates, markup is only written as is in element text; see
+/// [HtmlAttrEscaper](struct.HtmlAttrEscaper.html).
+pub struct Raw<T>(pub T);
+
+impl<T> Display for Raw<T>
 where
     T: Display,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let Html(Raw(ref inner)) = *self;
-        inner.fmt(f)
+        self.0.fmt(f)
     }
 }
 
-#[doc(hidden)]
-#[cfg(feature = "dynamic")]
-pub mod dynamic {
+/// Escapes everything written to it for HTML
+struct HtmlWriter<'a>(&'a mut dyn Write);
 
-    use std::collections::HashMap;
+impl Write for HtmlWriter<'_> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        escape_html(s, self.0)
+    }
+}
 
-    #[doc(hidden)]
-    pub fn get(path: &str, idx: usize) -> Option<String> {
-        use std::sync::Mutex;
+/// The entity written for each of the characters that are special in HTML
+fn html_entity(byte: u8) -> Option<&'static str> {
+    match byte {
+        b'<' => Some("&lt;"),
+        b'>' => Some("&gt;"),
+        b'&' => Some("&amp;"),
+        b'"' => Some("&quot;"),
+        b'\'' => Some("&#x27;"),
+        b'/' => Some("&#x2f;"),
+        _ => None,
+    }
+}
 
-        lazy_static::lazy_static! {
-            static ref MAP: Mutex<HashMap<String, HashMap<usize, String>>> = Mutex::new(HashMap::new());
-        }
+/// Writes `input` to `writer`, replacing the characters that are special in HTML with entities
+fn escape_html(input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+    let bytes = input.as_bytes();
+    let mut from = 0;
 
-        if let Ok(lock) = MAP.lock() {
-            if let Some(inner_map) = lock.get(path) {
-                return inner_map.get(&idx).cloned();
-            }
-        }
+    // The special characters are all ASCII, so `idx` is always on a char boundary
+    while let Some(idx) = find_html_special(bytes, from) {
+        writer.write_str(&input[from..idx])?;
+        writer.write_str(html_entity(bytes[idx]).unwrap_or_default())?;
+        from = idx + 1;
+    }
 
-        if let Ok(mut lock) = MAP.lock() {
-            if let Ok(inner_map) = parse(path) {
-                lock.insert(path.into(), inner_map.clone());
-                if let Some(out) = inner_map.get(&idx) {
-                    return Some(out.clone());
-                }
-            }
+    writer.write_str(&input[from..])
+}
+
+/// Finds the first byte at or after `from` that `html_entity` replaces
+///
+/// Text without special characters is skipped a word at a time: a word holds one of the special bytes if XOR-ing that
+/// byte into every lane leaves a zero lane, and `(x - 0x0101..) & !x & 0x8080..` is non-zero only for words with a zero
+/// byte.
+fn find_html_special(bytes: &[u8], mut from: usize) -> Option<usize> {
+    const WORD: usize = std::mem::size_of::<usize>();
+    const LO: usize = usize::MAX / 255;
+    const HI: usize = LO << 7;
+
+    while let Some(chunk) = bytes.get(from..from + WORD) {
+        let word = usize::from_ne_bytes(std::convert::TryInto::try_into(chunk).unwrap_or_default());
+        let zeroes = [b'<', b'>', b'&', b'"', b'\'', b'/'].iter().fold(0, |acc, &byte| {
+            let lanes = word ^ (LO * byte as usize);
+            acc | (lanes.wrapping_sub(LO) & !lanes)
+        });
+
+        if zeroes & HI != 0 {
+            break;
         }
 
-        None
+        from += WORD;
     }
 
-    fn parse(path: &str) -> erst_shared::err::Result<HashMap<usize, String>> {
-        use erst_shared::{
-            exp::Parser as _,
-            parser::{ErstParser, Rule},
-        };
+    bytes[from..].iter().position(|&byte| html_entity(byte).is_some()).map(|idx| from + idx)
+}
 
-        let template = std::fs::read_to_string(&path)?;
+/// The escaper for `type = "xml"`
+///
+/// Escapes the five predefined XML entities and replaces characters that aren't allowed in XML 1.0 (e.g., most ASCII
+/// control characters) with U+FFFD.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct XmlEscaper;
 
-        let pairs = ErstParser::parse(Rule::template, &template)
-            .map_err(|e| erst_shared::err::Error::Parse(e.to_string()))?;
+impl Escaper for XmlEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        let mut from = 0;
 
-        let mut map = HashMap::new();
+        for (idx, chr) in input.char_indices() {
+            let rep = match chr {
+                '<' => "&lt;",
+                '>' => "&gt;",
+                '&' => "&amp;",
+                '"' => "&quot;",
+                '\'' => "&apos;",
+                chr if !is_xml_char(chr) => "\u{FFFD}",
+                _ => continue,
+            };
 
-        for (idx, pair) in pairs.enumerate() {
-            match pair.as_rule() {
-                Rule::text => {
-                    map.insert(idx, pair.as_str().into());
-                }
-                _ => {}
-            }
+            writer.write_str(&input[from..idx])?;
+            writer.write_str(rep)?;
+            from = idx + chr.len_utf8();
         }
-        Ok(map)
+
+        writer.write_str(&input[from..])
     }
+}
+
+/// Whether `chr` matches the `Char` production of XML 1.0
+fn is_xml_char(chr: char) -> bool {
+    match chr {
+        '\t' | '\n' | '\r' => true,
+        '\u{FFFE}' | '\u{FFFF}' => false,
+        chr => chr >= ' ',
+    }
+}
 
+/// Wrap any `Display` content in this tuple struct to write it as an XML CDATA section
+///
+/// Any `]]>` in the content is split across two sections, and characters that aren't allowed in XML are replaced like
+/// with [XmlEscaper](struct.XmlEscaper.html).
+pub struct Cdata<T>(pub T);
+
+impl<T> Display for Cdata<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let content = self.0.to_string();
+
+        f.write_str("<![CDATA[")?;
+        for chr in content.replace("]]>", "]]]]><![CDATA[>").chars() {
+            f.write_char(if is_xml_char(chr) { chr } else { '\u{FFFD}' })?;
+        }
+        f.write_str("]]>")
+    }
+}
+
+impl<T> Markup<XmlEscaper> for Cdata<T> where T: Display {}
+
+/// The escaper for `type = "latex"`
+///
+/// Writes LaTeX's special characters (`\ { } $ & # % _ ^ ~ < >`) as commands or escapes that print them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LatexEscaper;
+
+impl Escaper for LatexEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        let mut from = 0;
+
+        for (idx, chr) in input.char_indices() {
+            let rep = match chr {
+                '\\' => "\\textbackslash{}",
+                '{' => "\\{",
+                '}' => "\\}",
+                '$' => "\\$",
+                '&' => "\\&",
+                '#' => "\\#",
+                '%' => "\\%",
+                '_' => "\\_",
+                '^' => "\\textasciicircum{}",
+                '~' => "\\textasciitilde{}",
+                '<' => "\\textless{}",
+                '>' => "\\textgreater{}",
+                _ => continue,
+            };
+
+            writer.write_str(&input[from..idx])?;
+            writer.write_str(rep)?;
+            from = idx + chr.len_utf8();
+        }
+
+        writer.write_str(&input[from..])
+    }
+}
+
+/// The escaper for `type = "markdown"`
+///
+/// Backslash-escapes every ASCII punctuation character, so values are always written as plain text (no emphasis,
+/// links, code spans, HTML or entities).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MarkdownEscaper;
+
+impl Escaper for MarkdownEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        let mut from = 0;
+
+        for (idx, chr) in input.char_indices() {
+            if !chr.is_ascii_punctuation() {
+                continue;
+            }
+
+            writer.write_str(&input[from..idx])?;
+            writer.write_char('\\')?;
+            writer.write_char(chr)?;
+            from = idx + chr.len_utf8();
+        }
+
+        writer.write_str(&input[from..])
+    }
+}
+
+/// The escaper for `type = "json"`
+///
+/// Writes values as the contents of a JSON string (without the surrounding quotes), so expressions go between quotes
+/// in the template: `"name": "<%= self.name %>"`. `<`, `>`, `&`, `'` and `` ` `` are escaped too, so the output is safe
+/// inside a `<script>` element, even between another kind of JavaScript quotes. Use [Json](struct.Json.html) to write a value as a complete JSON literal.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonEscaper;
+
+impl Escaper for JsonEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        let mut from = 0;
+
+        for (idx, chr) in input.char_indices() {
+            // `None` is written as a `\uXXXX` escape
+            let rep = match chr {
+                '"' => Some("\\\""),
+                '\\' => Some("\\\\"),
+                '\n' => Some("\\n"),
+                '\r' => Some("\\r"),
+                '\t' => Some("\\t"),
+                '\u{8}' => Some("\\b"),
+                '\u{c}' => Some("\\f"),
+                '<' | '>' | '&' | '\'' | '`' | '\u{2028}' | '\u{2029}' => None,
+                chr if chr < ' ' => None,
+                _ => continue,
+            };
+
+            writer.write_str(&input[from..idx])?;
+            match rep {
+                Some(rep) => writer.write_str(rep)?,
+                None => write!(writer, "\\u{:04x}", chr as u32)?,
+            }
+            from = idx + chr.len_utf8();
+        }
+
+        writer.write_str(&input[from..])
+    }
+}
+
+/// A value that can be written as a JSON literal with [Json](struct.Json.html)
+pub trait ToJson {
+    fn write_json(&self, writer: &mut dyn Write) -> std::fmt::Result;
+}
+
+impl ToJson for str {
+    fn write_json(&self, writer: &mut dyn Write) -> std::fmt::Result {
+        writer.write_char('"')?;
+        JsonEscaper.escape(self, writer)?;
+        writer.write_char('"')
+    }
+}
+
+impl ToJson for String {
+    fn write_json(&self, writer: &mut dyn Write) -> std::fmt::Result {
+        self.as_str().write_json(writer)
+    }
+}
+
+impl ToJson for char {
+    fn write_json(&self, writer: &mut dyn Write) -> std::fmt::Result {
+        self.encode_utf8(&mut [0; 4]).write_json(writer)
+    }
+}
+
+impl ToJson for bool {
+    fn write_json(&self, writer: &mut dyn Write) -> std::fmt::Result {
+        write!(writer, "{}", self)
+    }
+}
+
+macro_rules! impl_to_json_for_int {
+    ($($ty:ty),*) => {
+        $(
+            impl ToJson for $ty {
+                fn write_json(&self, writer: &mut dyn Write) -> std::fmt::Result {
+                    write!(writer, "{}", self)
+                }
+            }
+        )*
+    };
+}
+
+impl_to_json_for_int!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+macro_rules! impl_to_json_for_float {
+    ($($ty:ty),*) => {
+        $(
+            /// Non-finite numbers can't be represented in JSON, so they're written as `null`
+            impl ToJson for $ty {
+                fn write_json(&self, writer: &mut dyn Write) -> std::fmt::Result {
+                    if self.is_finite() {
+                        write!(writer, "{:?}", self)
+                    } else {
+                        writer.write_str("null")
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_to_json_for_float!(f32, f64);
+
+impl<T> ToJson for Option<T>
+where
+    T: ToJson,
+{
+    fn write_json(&self, writer: &mut dyn Write) -> std::fmt::Result {
+        match self {
+            Some(inner) => inner.write_json(writer),
+            None => writer.write_str("null"),
+        }
+    }
+}
+
+impl<T> ToJson for [T]
+where
+    T: ToJson,
+{
+    fn write_json(&self, writer: &mut dyn Write) -> std::fmt::Result {
+        writer.write_char('[')?;
+        for (idx, item) in self.iter().enumerate() {
+            if idx > 0 {
+                writer.write_char(',')?;
+            }
+            item.write_json(writer)?;
+        }
+        writer.write_char(']')
+    }
+}
+
+impl<T> ToJson for Vec<T>
+where
+    T: ToJson,
+{
+    fn write_json(&self, writer: &mut dyn Write) -> std::fmt::Result {
+        self.as_slice().write_json(writer)
+    }
+}
+
+impl<T> ToJson for &T
+where
+    T: ToJson + ?Sized,
+{
+    fn write_json(&self, writer: &mut dyn Write) -> std::fmt::Result {
+        (**self).write_json(writer)
+    }
+}
+
+/// Wrap a value in this tuple struct to write it as a complete JSON literal (e.g., a quoted string, a number or `null`)
+pub struct Json<T>(pub T);
+
+impl<T> Display for Json<T>
+where
+    T: ToJson,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.write_json(f)
+    }
+}
+
+impl<T> Markup<JsonEscaper> for Json<T> where T: ToJson {}
+
+impl<T> Markup<JsValueEscaper> for Json<T> where T: ToJson {}
+
+/// The escaper for `type = "js"`, for values inside JavaScript strings
+///
+/// Following OWASP's rules for JavaScript string contexts, every character below 256 except ASCII letters, digits, `,`,
+/// `.` and `_` is written as a `\xHH` escape, and U+2028 and U+2029 as `\uHHHH`, so a value can't end the string
+/// (or the `<script>` element) it's written in.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsEscaper;
+
+impl Escaper for JsEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        let mut from = 0;
+
+        for (idx, chr) in input.char_indices() {
+            if chr.is_ascii_alphanumeric() || matches!(chr, ',' | '.' | '_') {
+                continue;
+            }
+
+            writer.write_str(&input[from..idx])?;
+            match chr as u32 {
+                code @ 0..=0xff => write!(writer, "\\x{:02x}", code)?,
+                code @ (0x2028 | 0x2029) => write!(writer, "\\u{:04x}", code)?,
+                _ => writer.write_char(chr)?,
+            }
+            from = idx + chr.len_utf8();
+        }
+
+        writer.write_str(&input[from..])
+    }
+}
+
+/// The escaper for `type = "css"`, for values inside CSS strings and property values
+///
+/// Following OWASP's rules for CSS contexts, every character below 256 except ASCII letters and digits is written as a
+/// `\HH ` escape (the trailing space ends the escape, so a following hex digit isn't read as part of it).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CssEscaper;
+
+impl Escaper for CssEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        let mut from = 0;
+
+        for (idx, chr) in input.char_indices() {
+            if chr.is_ascii_alphanumeric() || chr as u32 > 0xff {
+                continue;
+            }
+
+            writer.write_str(&input[from..idx])?;
+            write!(writer, "\\{:x} ", chr as u32)?;
+            from = idx + chr.len_utf8();
+        }
+
+        writer.write_str(&input[from..])
+    }
+}
+
+/// The context of expressions outside of a string in a `<script>` element of an `html` template
+///
+/// Values there are written as JSON literals, so they keep their JavaScript type: anything implementing
+/// [ToJson](trait.ToJson.html) (strings, numbers, `bool`, `Option`, ...) is written like [Json](struct.Json.html)
+/// would, and values implementing `Markup<JsValueEscaper>` (e.g., `Json` itself) are written as is. Other values are
+/// a compile error. JSON strings escape `<`, `>`, `&`, `'` and `` ` ``, so they can't end the `<script>` element, or a
+/// JavaScript string if the template's code is misread.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsValueEscaper;
+
+/// Wrap any `Display` content in this tuple struct to escape it for a JavaScript string, e.g., in an `html` template:
+/// `<script>var name = '<%= erst::Js(&self.name) %>';</script>`
+///
+/// See [JsEscaper](struct.JsEscaper.html). The escaped output contains no HTML special characters, so it isn't escaped
+/// again by `html` templates.
+pub struct Js<T>(pub T);
+
+impl<T> Display for Js<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        JsEscaper.write_value(&self.0, f)
+    }
+}
+
+impl<T> Markup<HtmlEscaper> for Js<T> where T: Display {}
+
+impl<T> Markup<JsEscaper> for Js<T> where T: Display {}
+
+/// Wrap any `Display` content in this tuple struct to escape it for CSS, e.g., in an `html` template:
+/// `<style>.user::after { content: "<%= erst::Css(&self.name) %>"; }</style>`
+///
+/// See [CssEscaper](struct.CssEscaper.html). The escaped output contains no HTML special characters, so it isn't
+/// escaped again by `html` templates.
+pub struct Css<T>(pub T);
+
+impl<T> Display for Css<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        CssEscaper.write_value(&self.0, f)
+    }
+}
+
+impl<T> Markup<HtmlEscaper> for Css<T> where T: Display {}
+
+impl<T> Markup<CssEscaper> for Css<T> where T: Display {}
+
+/// The escaper for `type = "url"`, and for [UrlComponent](struct.UrlComponent.html)
+///
+/// Percent-encodes everything except ASCII letters, digits, `-`, `.`, `_` and `~`, so a value can't add path segments,
+/// query parameters or a fragment.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UrlComponentEscaper;
+
+impl Escaper for UrlComponentEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        percent_encode(input, "-._~", writer)
+    }
+}
+
+/// Percent-encodes everything in `input` except ASCII letters, digits and the characters in `keep`
+fn percent_encode(input: &str, keep: &str, writer: &mut dyn Write) -> std::fmt::Result {
+    let mut from = 0;
+
+    for (idx, chr) in input.char_indices() {
+        if chr.is_ascii_alphanumeric() || keep.contains(chr) {
+            continue;
+        }
+
+        writer.write_str(&input[from..idx])?;
+        percent_encode_char(chr, writer)?;
+        from = idx + chr.len_utf8();
+    }
+
+    writer.write_str(&input[from..])
+}
+
+/// Writes the UTF-8 bytes of `chr` as `%XX` escapes
+fn percent_encode_char(chr: char, writer: &mut dyn Write) -> std::fmt::Result {
+    for byte in chr.encode_utf8(&mut [0; 4]).bytes() {
+        write!(writer, "%{:02X}", byte)?;
+    }
+    Ok(())
+}
+
+/// The escaper for expressions at the start of a URL attribute (e.g., `href` or `src`) in an `html` template
+///
+/// URLs with a scheme other than `http`, `https` or `mailto` (e.g., `javascript:`) are replaced with
+/// `about:invalid#erst-unsafe-url`. Characters that aren't allowed in URLs are percent-encoded, and `&` is written as
+/// `&amp;`. URLs that are already encoded (e.g., [Query](struct.Query.html)) are only escaped for the attribute.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HtmlUrlEscaper;
+
+impl Escaper for HtmlUrlEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        let mut from = 0;
+
+        for (idx, chr) in input.char_indices() {
+            if chr.is_ascii_alphanumeric() || "-._~:/?#[]@!$()*+,;=%".contains(chr) {
+                continue;
+            }
+
+            writer.write_str(&input[from..idx])?;
+            match chr {
+                '&' => writer.write_str("&amp;")?,
+                chr => percent_encode_char(chr, writer)?,
+            }
+            from = idx + chr.len_utf8();
+        }
+
+        writer.write_str(&input[from..])
+    }
+
+    fn write_value(&self, value: &dyn Display, writer: &mut dyn Write) -> std::fmt::Result {
+        let url = value.to_string();
+        if is_safe_url(&url) {
+            self.escape(&url, writer)
+        } else {
+            writer.write_str("about:invalid#erst-unsafe-url")
+        }
+    }
+
+    fn write_markup(&self, value: &dyn Display, writer: &mut dyn Write) -> std::fmt::Result {
+        XmlEscaper.write_value(value, writer)
+    }
+}
+
+/// Whether `url` is relative or uses a scheme that can't run code
+fn is_safe_url(url: &str) -> bool {
+    match url.find([':', '/', '?', '#']) {
+        Some(idx) if url[idx..].starts_with(':') => {
+            let scheme = url[..idx].to_ascii_lowercase();
+            scheme == "http" || scheme == "https" || scheme == "mailto"
+        }
+        _ => true,
+    }
+}
+
+/// The escaper for expressions inside a URL attribute (after the start of the URL) in an `html` template
+///
+/// Percent-encodes values like [UrlComponentEscaper](struct.UrlComponentEscaper.html). Values that are already encoded
+/// (e.g., [Query](struct.Query.html)) are only escaped for the attribute.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HtmlUrlComponentEscaper;
+
+impl Escaper for HtmlUrlComponentEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        UrlComponentEscaper.escape(input, writer)
+    }
+
+    fn write_markup(&self, value: &dyn Display, writer: &mut dyn Write) -> std::fmt::Result {
+        XmlEscaper.write_value(value, writer)
+    }
+}
+
+/// Wrap any `Display` content in this tuple struct to percent-encode it as a URL component (e.g., a path segment or a
+/// query parameter)
+///
+/// See [UrlComponentEscaper](struct.UrlComponentEscaper.html).
+pub struct UrlComponent<T>(pub T);
+
+impl<T> Display for UrlComponent<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        UrlComponentEscaper.write_value(&self.0, f)
+    }
+}
+
+/// Wrap any `Display` content in this tuple struct to percent-encode it as a URL path, keeping any `/`
+pub struct UrlPath<T>(pub T);
+
+impl<T> Display for UrlPath<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        percent_encode(&self.0.to_string(), "-._~/", f)
+    }
+}
+
+/// A query string, built from key-value pairs that are percent-encoded when it's written
+///
+/// ```rust
+/// let query = erst::Query::new().param("q", "a&b c").param("page", 2);
+/// assert_eq!(query.to_string(), "q=a%26b%20c&page=2");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    pairs: Vec<(String, String)>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Query::default()
+    }
+
+    /// Adds a `key=value` pair
+    pub fn param(mut self, key: impl Display, value: impl Display) -> Self {
+        self.pairs.push((key.to_string(), value.to_string()));
+        self
+    }
+}
+
+impl<K, V> std::iter::FromIterator<(K, V)> for Query
+where
+    K: Display,
+    V: Display,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        iter.into_iter().fold(Query::new(), |query, (key, value)| query.param(key, value))
+    }
+}
+
+impl Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (idx, (key, value)) in self.pairs.iter().enumerate() {
+            if idx > 0 {
+                f.write_char('&')?;
+            }
+            UrlComponentEscaper.escape(key, f)?;
+            f.write_char('=')?;
+            UrlComponentEscaper.escape(value, f)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> Markup<UrlComponentEscaper> for UrlComponent<T> where T: Display {}
+
+impl<T> Markup<HtmlUrlEscaper> for UrlComponent<T> where T: Display {}
+
+impl<T> Markup<HtmlUrlComponentEscaper> for UrlComponent<T> where T: Display {}
+
+impl<T> Markup<UrlComponentEscaper> for UrlPath<T> where T: Display {}
+
+impl<T> Markup<HtmlUrlEscaper> for UrlPath<T> where T: Display {}
+
+impl<T> Markup<HtmlUrlComponentEscaper> for UrlPath<T> where T: Display {}
+
+impl Markup<UrlComponentEscaper> for Query {}
+
+impl Markup<HtmlUrlEscaper> for Query {}
+
+impl Markup<HtmlUrlComponentEscaper> for Query {}
+
+/// The escaper for `type = "sh"`, writing values as POSIX shell single-quoted strings
+///
+/// Every value is quoted (an empty value is written as `''`), and `'` is written as `'\''`. Shell arguments can't hold
+/// NUL characters, so rendering a value with one fails with `fmt::Error`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShellEscaper;
+
+impl Escaper for ShellEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        quote_escape(input, "'\\''", writer)
+    }
+
+    fn write_value(&self, value: &dyn Display, writer: &mut dyn Write) -> std::fmt::Result {
+        writer.write_char('\'')?;
+        write!(EscapeWriter { escaper: self, writer }, "{}", value)?;
+        writer.write_char('\'')
+    }
+}
+
+/// The escaper for `type = "sql"`, writing values as standard SQL string literals
+///
+/// Every value is quoted, and `'` is written as `''`. Backslashes aren't escaped, as in standard SQL (e.g., PostgreSQL
+/// with `standard_conforming_strings`, SQLite); don't use it with MySQL's default `sql_mode`. NUL characters aren't
+/// allowed in most databases' strings, so rendering a value with one fails with `fmt::Error`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SqlEscaper;
+
+impl Escaper for SqlEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        quote_escape(input, "''", writer)
+    }
+
+    fn write_value(&self, value: &dyn Display, writer: &mut dyn Write) -> std::fmt::Result {
+        writer.write_char('\'')?;
+        write!(EscapeWriter { escaper: self, writer }, "{}", value)?;
+        writer.write_char('\'')
+    }
+}
+
+/// Writes `input` with each `'` replaced by `quote`, failing on NUL characters
+fn quote_escape(input: &str, quote: &str, writer: &mut dyn Write) -> std::fmt::Result {
+    if input.contains('\0') {
+        return Err(std::fmt::Error);
+    }
+
+    let mut parts = input.split('\'');
+    if let Some(first) = parts.next() {
+        writer.write_str(first)?;
+    }
+    for part in parts {
+        writer.write_str(quote)?;
+        writer.write_str(part)?;
+    }
+    Ok(())
+}
+
+/// Wrap any `Display` content in this tuple struct to write it as a single-quoted shell string
+///
+/// See [ShellEscaper](struct.ShellEscaper.html).
+pub struct ShellQuote<T>(pub T);
+
+impl<T> Display for ShellQuote<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        ShellEscaper.write_value(&self.0, f)
+    }
+}
+
+impl<T> Markup<ShellEscaper> for ShellQuote<T> where T: Display {}
+
+/// Wrap any `Display` content in this tuple struct to write it as an SQL string literal
+///
+/// See [SqlEscaper](struct.SqlEscaper.html).
+pub struct SqlLiteral<T>(pub T);
+
+impl<T> Display for SqlLiteral<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        SqlEscaper.write_value(&self.0, f)
+    }
+}
+
+impl<T> Markup<SqlEscaper> for SqlLiteral<T> where T: Display {}
+
+/// The escaper for `type = "csv"`, writing each value as a single field, quoted as in RFC 4180
+///
+/// Values holding the delimiter, `"`, CR or LF are put between `"`, with `"` written as `""`; other values are written
+/// as is. The delimiter is `,` unless set with `#[template(type = "csv", delimiter = ';')]`.
+#[derive(Debug, Clone, Copy)]
+pub struct CsvEscaper {
+    delimiter: char,
+}
+
+impl CsvEscaper {
+    /// Creates an escaper for fields separated by `delimiter`
+    pub fn new(delimiter: char) -> Self {
+        CsvEscaper { delimiter }
+    }
+}
+
+impl Default for CsvEscaper {
+    fn default() -> Self {
+        CsvEscaper::new(',')
+    }
+}
+
+impl Escaper for CsvEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        let mut parts = input.split('"');
+        if let Some(first) = parts.next() {
+            writer.write_str(first)?;
+        }
+        for part in parts {
+            writer.write_str("\"\"")?;
+            writer.write_str(part)?;
+        }
+        Ok(())
+    }
+
+    fn write_value(&self, value: &dyn Display, writer: &mut dyn Write) -> std::fmt::Result {
+        // Whether the field needs quoting depends on the whole value
+        let value = value.to_string();
+
+        if value.contains([self.delimiter, '"', '\r', '\n']) {
+            writer.write_char('"')?;
+            self.escape(&value, writer)?;
+            writer.write_char('"')
+        } else {
+            writer.write_str(&value)
+        }
+    }
+}
+
+/// The escaper for `type = "rust"`, writing values as the contents of a Rust string literal
+///
+/// Expressions go between quotes in the template (`"<%= self.name %>"`); `"`, `\` and control characters are escaped.
+/// Use [RustStr](struct.RustStr.html), [RustChar](struct.RustChar.html) and [RustIdent](struct.RustIdent.html) to
+/// write complete literals and identifiers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RustEscaper;
+
+impl Escaper for RustEscaper {
+    fn escape(&self, input: &str, writer: &mut dyn Write) -> std::fmt::Result {
+        let mut from = 0;
+
+        for (idx, chr) in input.char_indices() {
+            if chr != '"' && chr != '\\' && !chr.is_control() {
+                continue;
+            }
+
+            writer.write_str(&input[from..idx])?;
+            write!(writer, "{}", chr.escape_default())?;
+            from = idx + chr.len_utf8();
+        }
+
+        writer.write_str(&input[from..])
+    }
+}
+
+/// Wrap any `Display` content in this tuple struct to write it as a Rust string literal
+pub struct RustStr<T>(pub T);
+
+impl<T> Display for RustStr<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_char('"')?;
+        RustEscaper.write_value(&self.0, f)?;
+        f.write_char('"')
+    }
+}
+
+impl<T> Markup<RustEscaper> for RustStr<T> where T: Display {}
+
+/// Wrap a `char` in this tuple struct to write it as a Rust char literal
+pub struct RustChar(pub char);
+
+impl Display for RustChar {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.0 {
+            '"' => f.write_str("'\"'"),
+            chr if chr.is_control() || chr == '\'' || chr == '\\' => {
+                write!(f, "'{}'", chr.escape_default())
+            }
+            chr => write!(f, "'{}'", chr),
+        }
+    }
+}
+
+impl Markup<RustEscaper> for RustChar {}
+
+/// Wrap any `Display` content in this tuple struct to write it as a Rust identifier
+///
+/// Keywords are written as raw identifiers (e.g., `r#type`). Rendering fails with `fmt::Error` if the content isn't a
+/// valid identifier, or is a keyword that can't be a raw identifier (`crate`, `self`, `Self`, `super` and `_`).
+pub struct RustIdent<T>(pub T);
+
+/// Keywords that have to be written as raw identifiers, as of the 2021 edition
+const RUST_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop",
+    "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "static",
+    "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual",
+    "where", "while", "yield",
+];
+
+impl<T> Display for RustIdent<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let ident = self.0.to_string();
+
+        let mut chars = ident.chars();
+        let valid = match chars.next() {
+            Some(first) => {
+                (first == '_' || first.is_alphabetic())
+                    && chars.all(|x| x == '_' || x.is_alphanumeric())
+            }
+            None => false,
+        };
+
+        if !valid || ["_", "crate", "self", "Self", "super"].contains(&ident.as_str()) {
+            return Err(std::fmt::Error);
+        }
+
+        if RUST_KEYWORDS.contains(&ident.as_str()) {
+            f.write_str("r#")?;
+        }
+        f.write_str(&ident)
+    }
+}
+
+impl<T> Markup<RustEscaper> for RustIdent<T> where T: Display {}
+
+/// Checks that `source` (e.g., the output of a `type = "rust"` template) parses as a Rust file
+#[cfg(feature = "rust")]
+pub fn check_rust(source: &str) -> Result<(), String> {
+    erst_shared::rust::check(source).map_err(|e| e.to_string())
+}
+
+/// Picks how an expression in an escaped template is written, using autoderef specialization
+///
+/// The generated code calls `(&&&erst::render::Value(&value, &escaper)).render_value(writer)`, which uses the first of
+/// these that applies: `Markup` values match `RenderMarkup` and are written verbatim, strings match `RenderStr` and are
+/// escaped without going through `Display`, and anything else falls back to `RenderEscaped`.
+#[doc(hidden)]
+pub mod render {
+    use super::{Escaper, Markup};
+    use std::fmt::{Display, Write};
+
+    pub struct Value<'a, T: ?Sized, E>(pub &'a T, pub &'a E);
+
+    /// The template file and line being rendered, set before each text or expression is written
+    #[derive(Default)]
+    pub struct Location(Option<(&'static str, usize)>);
+
+    impl Location {
+        #[inline]
+        pub fn set(&mut self, path: &'static str, line: usize) {
+            self.0 = Some((path, line));
+        }
+
+        pub(crate) fn error(self, cause: std::fmt::Error) -> super::RenderError {
+            super::RenderError { location: self.0, cause }
+        }
+    }
+
+    pub trait RenderMarkup {
+        fn render_value(&self, writer: &mut dyn Write) -> std::fmt::Result;
+    }
+
+    impl<T, E> RenderMarkup for &&Value<'_, T, E>
+    where
+        T: Markup<E> + ?Sized,
+        E: Escaper,
+    {
+        fn render_value(&self, writer: &mut dyn Write) -> std::fmt::Result {
+            self.1.write_markup(&self.0, writer)
+        }
+    }
+
+    pub trait RenderStr {
+        fn render_value(&self, writer: &mut dyn Write) -> std::fmt::Result;
+    }
+
+    impl<T, E> RenderStr for &Value<'_, T, E>
+    where
+        T: AsStr + ?Sized,
+        E: Escaper,
+    {
+        fn render_value(&self, writer: &mut dyn Write) -> std::fmt::Result {
+            self.1.write_str(self.0.as_str(), writer)
+        }
+    }
+
+    /// An expression in `<script>` code outside of a string, written as a JSON literal
+    pub struct JsonValue<'a, T: ?Sized>(pub &'a T);
+
+    impl<T> RenderMarkup for &JsonValue<'_, T>
+    where
+        T: Markup<super::JsValueEscaper> + ?Sized,
+    {
+        fn render_value(&self, writer: &mut dyn Write) -> std::fmt::Result {
+            write!(writer, "{}", self.0)
+        }
+    }
+
+    pub trait RenderJson {
+        fn render_value(&self, writer: &mut dyn Write) -> std::fmt::Result;
+    }
+
+    impl<T> RenderJson for JsonValue<'_, T>
+    where
+        T: super::ToJson + ?Sized,
+    {
+        fn render_value(&self, writer: &mut dyn Write) -> std::fmt::Result {
+            self.0.write_json(writer)
+        }
+    }
+
+    pub trait RenderEscaped {
+        fn render_value(&self, writer: &mut dyn Write) -> std::fmt::Result;
+    }
+
+    impl<T, E> RenderEscaped for Value<'_, T, E>
+    where
+        T: Display + ?Sized,
+        E: Escaper,
+    {
+        fn render_value(&self, writer: &mut dyn Write) -> std::fmt::Result {
+            self.1.write_value(&self.0, writer)
+        }
+    }
+
+    /// String types, whose `Display` output is the string itself
+    pub trait AsStr {
+        fn as_str(&self) -> &str;
+    }
+
+    impl AsStr for str {
+        fn as_str(&self) -> &str {
+            self
+        }
+    }
+
+    impl AsStr for String {
+        fn as_str(&self) -> &str {
+            self
+        }
+    }
+
+    impl AsStr for std::borrow::Cow<'_, str> {
+        fn as_str(&self) -> &str {
+            self
+        }
+    }
+
+    impl<T> AsStr for &T
+    where
+        T: AsStr + ?Sized,
+    {
+        fn as_str(&self) -> &str {
+            (**self).as_str()
+        }
+    }
+
+    impl<T> AsStr for Box<T>
+    where
+        T: AsStr + ?Sized,
+    {
+        fn as_str(&self) -> &str {
+            (**self).as_str()
+        }
+    }
+
+    impl<T> AsStr for std::rc::Rc<T>
+    where
+        T: AsStr + ?Sized,
+    {
+        fn as_str(&self) -> &str {
+            (**self).as_str()
+        }
+    }
+
+    impl<T> AsStr for std::sync::Arc<T>
+    where
+        T: AsStr + ?Sized,
+    {
+        fn as_str(&self) -> &str {
+            (**self).as_str()
+        }
+    }
+}
+
+#[doc(hidden)]
+#[cfg(feature = "dynamic")]
+pub mod dynamic {
+
+    use std::collections::HashMap;
+
+    #[doc(hidden)]
+    pub fn get(templates_dir: &str, path: &str, idx: usize) -> Option<String> {
+        use std::sync::Mutex;
+
+        lazy_static::lazy_static! {
+            static ref MAP: Mutex<HashMap<String, HashMap<usize, String>>> = Mutex::new(HashMap::new());
+        }
+
+        let key = std::path::Path::new(templates_dir).join(path).display().to_string();
+
+        if let Ok(lock) = MAP.lock() {
+            if let Some(inner_map) = lock.get(&key) {
+                return inner_map.get(&idx).cloned();
+            }
+        }
+
+        if let Ok(mut lock) = MAP.lock() {
+            if let Ok(inner_map) = parse(templates_dir, path) {
+                lock.insert(key, inner_map.clone());
+                if let Some(out) = inner_map.get(&idx) {
+                    return Some(out.clone());
+                }
+            }
+        }
+
+        None
+    }
+
+    fn parse(templates_dir: &str, path: &str) -> erst_shared::err::Result<HashMap<usize, String>> {
+        use erst_shared::parser::{Kind, Template};
+
+        let template = Template::resolve(templates_dir.as_ref(), path)?;
+
+        let map = template
+            .segments
+            .into_iter()
+            .filter(|x| x.kind == Kind::Text)
+            .map(|x| x.content)
+            .enumerate()
+            .collect();
+
+        Ok(map)
+    }
 }
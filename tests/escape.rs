@@ -0,0 +1,307 @@
+use erst::{Css, Js};
+
+/// Payloads that try to break out of a JavaScript string or a `<script>` element
+const JS_PAYLOADS: &[&str] = &[
+    "';alert(1)//",
+    "\";alert(1)//",
+    "</script><script>alert(1)</script>",
+    "\\';alert(1)//",
+    "`${alert(1)}`",
+    "\u{2028}alert(1)\u{2029}",
+    "<!--<script>",
+    "\nalert(1)",
+];
+
+/// Payloads that try to break out of a CSS string or a `<style>` element
+const CSS_PAYLOADS: &[&str] = &[
+    "\"; background: url(javascript:alert(1)); \"",
+    "'; } body { background: red; } .x { content: '",
+    "</style><script>alert(1)</script>",
+    "expression(alert(1))",
+    "\\\"; color: red",
+    "/* */ @import 'evil.css';",
+];
+
+#[test]
+fn js_payloads() {
+    for payload in JS_PAYLOADS {
+        let escaped = Js(payload).to_string();
+        assert!(
+            escaped
+                .chars()
+                .all(|x| x.is_ascii_alphanumeric() || matches!(x, '\\' | ',' | '.' | '_')),
+            "{:?} escaped to {:?}",
+            payload,
+            escaped
+        );
+    }
+}
+
+#[test]
+fn js_escapes() {
+    assert_eq!(Js("a.b_c,1").to_string(), "a.b_c,1");
+    assert_eq!(Js("';alert(1)//").to_string(), r"\x27\x3balert\x281\x29\x2f\x2f");
+    assert_eq!(Js("</script>").to_string(), r"\x3c\x2fscript\x3e");
+    assert_eq!(Js("\u{0}\n\u{e9}").to_string(), r"\x00\x0a\xe9");
+    assert_eq!(Js("\u{2028}\u{2029}").to_string(), r"\u2028\u2029");
+    assert_eq!(Js("\u{263a}\u{1f600}").to_string(), "\u{263a}\u{1f600}");
+}
+
+#[test]
+fn json_payloads() {
+    for payload in JS_PAYLOADS {
+        let json = erst::Json(payload).to_string();
+        let inner = &json[1..json.len() - 1];
+        assert!(
+            !inner.contains(['\'', '`', '<', '\n', '\u{2028}']),
+            "{:?} escaped to {:?}",
+            payload,
+            json
+        );
+    }
+    assert_eq!(erst::Json("';`").to_string(), r#""\u0027;\u0060""#);
+}
+
+#[test]
+fn css_payloads() {
+    for payload in CSS_PAYLOADS {
+        let escaped = Css(payload).to_string();
+        assert!(
+            escaped.chars().all(|x| x.is_ascii_alphanumeric() || matches!(x, '\\' | ' ')),
+            "{:?} escaped to {:?}",
+            payload,
+            escaped
+        );
+    }
+}
+
+#[test]
+fn css_escapes() {
+    assert_eq!(Css("red").to_string(), "red");
+    assert_eq!(Css("\"; color: red").to_string(), r"\22 \3b \20 color\3a \20 red");
+    assert_eq!(Css("</style>").to_string(), r"\3c \2f style\3e ");
+    assert_eq!(Css("\u{0}\u{e9}\u{263a}").to_string(), "\\0 \\e9 \u{263a}");
+}
+
+/// Characters that are special in shell or SQL strings
+const QUOTING_ALPHABET: &[char] =
+    &['a', '\'', '"', '\\', '\n', '\r', '\t', '$', '`', ' ', '!', ';', '%', 'é'];
+
+/// Every string of up to three characters from `QUOTING_ALPHABET`
+fn quoting_inputs() -> Vec<String> {
+    let mut out = vec![String::new()];
+    let mut last = vec![String::new()];
+
+    for _ in 0..3 {
+        last = last
+            .iter()
+            .flat_map(|prefix| QUOTING_ALPHABET.iter().map(move |chr| format!("{}{}", prefix, chr)))
+            .collect();
+        out.extend(last.iter().cloned());
+    }
+
+    out
+}
+
+#[test]
+fn shell_quote() {
+    use erst::ShellQuote;
+
+    assert_eq!(ShellQuote("").to_string(), "''");
+    assert_eq!(ShellQuote("a b").to_string(), "'a b'");
+    assert_eq!(ShellQuote("it's").to_string(), r"'it'\''s'");
+    assert_eq!(ShellQuote("''").to_string(), r"''\'''\'''");
+    assert_eq!(ShellQuote("a\nb").to_string(), "'a\nb'");
+    assert_eq!(ShellQuote("$(rm -rf /) `x` \\").to_string(), "'$(rm -rf /) `x` \\'");
+}
+
+#[test]
+fn shell_quote_round_trip() {
+    use erst::ShellQuote;
+
+    let inputs = quoting_inputs();
+    let script = format!(
+        "printf '%s\\0' {}",
+        inputs.iter().map(|x| ShellQuote(x).to_string()).collect::<Vec<_>>().join(" ")
+    );
+
+    let output = match std::process::Command::new("sh").arg("-c").arg(&script).output() {
+        Ok(output) => output,
+        // No POSIX shell to check against
+        Err(_) => return,
+    };
+
+    let parsed = String::from_utf8(output.stdout).unwrap();
+    let parsed = parsed.split('\0').collect::<Vec<_>>();
+    assert_eq!(parsed.len(), inputs.len() + 1);
+    for (input, parsed) in inputs.iter().zip(parsed) {
+        assert_eq!(input, parsed);
+    }
+}
+
+#[test]
+fn sql_literal() {
+    use erst::SqlLiteral;
+
+    assert_eq!(SqlLiteral("").to_string(), "''");
+    assert_eq!(SqlLiteral("O'Brien").to_string(), "'O''Brien'");
+    assert_eq!(SqlLiteral("'; DROP TABLE users; --").to_string(), "'''; DROP TABLE users; --'");
+    assert_eq!(SqlLiteral("a\\'b").to_string(), "'a\\''b'");
+    assert_eq!(SqlLiteral("a\r\nb").to_string(), "'a\r\nb'");
+}
+
+#[test]
+fn sql_literal_round_trip() {
+    use erst::SqlLiteral;
+
+    for input in quoting_inputs() {
+        let literal = SqlLiteral(&input).to_string();
+        let inner = &literal[1..literal.len() - 1];
+        assert!(literal.starts_with('\'') && literal.ends_with('\''), "{:?}", literal);
+        assert!(!inner.replace("''", "").contains('\''), "{:?}", literal);
+        assert_eq!(inner.replace("''", "'"), input);
+    }
+}
+
+#[test]
+fn quoting_rejects_nul() {
+    use erst::{ShellQuote, SqlLiteral};
+    use std::fmt::Write;
+
+    for input in &["\0", "a\0", "'\0'"] {
+        assert!(write!(String::new(), "{}", ShellQuote(input)).is_err());
+        assert!(write!(String::new(), "{}", SqlLiteral(input)).is_err());
+    }
+}
+
+#[test]
+fn rust_literals() {
+    use erst::{RustChar, RustStr};
+
+    assert_eq!(RustStr("plain").to_string(), r#""plain""#);
+    assert_eq!(RustStr("\"quoted\" \\ 'single'").to_string(), r#""\"quoted\" \\ 'single'""#);
+    assert_eq!(RustStr("a\nb\r\t\0\u{7f}é").to_string(), "\"a\\nb\\r\\t\\u{0}\\u{7f}é\"");
+    assert_eq!(RustChar('a').to_string(), "'a'");
+    assert_eq!(RustChar('\'').to_string(), r"'\''");
+    assert_eq!(RustChar('"').to_string(), r#"'"'"#);
+    assert_eq!(RustChar('\\').to_string(), r"'\\'");
+    assert_eq!(RustChar('\n').to_string(), r"'\n'");
+}
+
+#[test]
+fn rust_idents() {
+    use erst::RustIdent;
+    use std::fmt::Write;
+
+    assert_eq!(RustIdent("name").to_string(), "name");
+    assert_eq!(RustIdent("_private2").to_string(), "_private2");
+    assert_eq!(RustIdent("type").to_string(), "r#type");
+    assert_eq!(RustIdent("async").to_string(), "r#async");
+    assert_eq!(RustIdent("café").to_string(), "café");
+
+    for invalid in &["", "_", "1abc", "a-b", "a b", "self", "Self", "crate", "super", "r#x"] {
+        assert!(write!(String::new(), "{}", RustIdent(invalid)).is_err(), "{:?}", invalid);
+    }
+}
+
+#[cfg(feature = "rust")]
+#[test]
+fn check_rust() {
+    assert!(erst::check_rust("fn main() { let x = 1; }").is_ok());
+    assert!(erst::check_rust("fn main() { let x = ; }").is_err());
+    assert!(erst::check_rust("fn f(x: Option<u8>) -> u8 { let Some(y) = x else { return 0 }; y }")
+        .is_ok());
+    assert!(erst::check_rust("async fn f() { let x = async move { 1 }.await; }").is_ok());
+}
+
+#[test]
+fn latex() {
+    use erst::{Escaper, LatexEscaper};
+
+    let mut out = String::new();
+    LatexEscaper.escape(r"50% of $5 & #1_a^b~{c}\d <e>", &mut out).unwrap();
+    assert_eq!(
+        out,
+        r"50\% of \$5 \& \#1\_a\textasciicircum{}b\textasciitilde{}\{c\}\textbackslash{}d \textless{}e\textgreater{}"
+    );
+}
+
+#[test]
+fn markdown() {
+    use erst::{Escaper, MarkdownEscaper};
+
+    let mut out = String::new();
+    MarkdownEscaper.escape("*a* _b_ `c` [d](e) # f\n1. <g> &amp; é", &mut out).unwrap();
+    assert_eq!(out, "\\*a\\* \\_b\\_ \\`c\\` \\[d\\]\\(e\\) \\# f\n1\\. \\<g\\> \\&amp\\; é");
+}
+
+#[test]
+fn csv_fields() {
+    use erst::{CsvEscaper, Escaper};
+
+    let cases = [
+        ("plain", "plain"),
+        ("", ""),
+        ("Smith, John", "\"Smith, John\""),
+        ("say \"hi\"", "\"say \"\"hi\"\"\""),
+        ("two\nlines", "\"two\nlines\""),
+        ("cr\r", "\"cr\r\""),
+        ("a;b", "a;b"),
+    ];
+
+    for (input, expected) in &cases {
+        let mut out = String::new();
+        CsvEscaper::default().write_value(input, &mut out).unwrap();
+        assert_eq!(&out, expected);
+    }
+
+    let mut out = String::new();
+    CsvEscaper::new(';').write_value(&"a;b", &mut out).unwrap();
+    assert_eq!(out, "\"a;b\"");
+}
+
+/// A byte-at-a-time HTML escaper, to check the word-at-a-time scan against
+fn html_reference(input: &str) -> String {
+    input
+        .chars()
+        .map(|chr| match chr {
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '&' => "&amp;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#x27;".to_string(),
+            '/' => "&#x2f;".to_string(),
+            chr => chr.to_string(),
+        })
+        .collect()
+}
+
+#[test]
+fn html_escapes() {
+    use erst::{Escaper, HtmlEscaper};
+
+    // Every special character (and a few near misses) at every offset within and across words, next to multi-byte chars
+    let mut inputs = vec![String::new()];
+    for special in &["<", ">", "&", "\"", "'", "/", "=", "?", "\0", "\u{ff}", "é", "€"] {
+        for len in 0..20 {
+            for at in 0..=len {
+                let mut input = "a".repeat(len);
+                input.insert_str(at, special);
+                inputs.push(input.clone());
+                inputs.push(input + "é<");
+            }
+        }
+    }
+
+    for input in &inputs {
+        let expected = html_reference(input);
+
+        let mut out = String::new();
+        HtmlEscaper.write_str(input, &mut out).unwrap();
+        assert_eq!(out, expected, "{:?}", input);
+
+        let mut out = String::new();
+        HtmlEscaper.write_value(&format_args!("{}", input), &mut out).unwrap();
+        assert_eq!(out, expected, "{:?}", input);
+    }
+}
@@ -0,0 +1,694 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(path = "tests/comments.erst", type = "html")]
+pub struct Comments<'a> {
+    pub collection: Vec<&'a str>,
+}
+
+#[test]
+fn comments_are_stripped() {
+    assert_eq!(
+        Comments { collection: vec!["a", "b"] }.to_string(),
+        "<ul>\n    \n        <li>a</li>\n    \n        <li>b</li>\n    \n</ul>\n"
+    );
+}
+
+#[derive(Template)]
+#[template(path = "tests/literals.erst")]
+pub struct Literals;
+
+#[test]
+fn literal_delimiters() {
+    assert_eq!(Literals.to_string(), "<% for x in items %>\n<%= x %>\n<% end %>\n");
+}
+
+#[derive(Template)]
+#[template(path = "tests/raw.erst", type = "html")]
+pub struct RawOutput<'a>(&'a str);
+
+#[test]
+fn raw_output() {
+    assert_eq!(RawOutput("<b>").to_string(), "&lt;b&gt;|<b>|<b>\n");
+}
+
+#[derive(Template)]
+#[template(path = "tests/page.erst", type = "html")]
+pub struct Page<'a> {
+    pub title: &'a str,
+    pub body: &'a str,
+}
+
+#[test]
+fn extends() {
+    assert_eq!(
+        Page { title: "Home", body: "<hi>" }.to_string(),
+        "<title>Home | Site</title>\n<main>main: &lt;hi&gt;</main>\n<footer>footer</footer>\n"
+    );
+}
+
+#[derive(Template)]
+#[template(path = "tests/includes.erst", type = "html")]
+pub struct Includes<'a> {
+    pub items: Vec<&'a str>,
+}
+
+#[test]
+fn includes() {
+    assert_eq!(
+        Includes { items: vec!["a", "<b>"] }.to_string(),
+        "<ul>\n<li>a</li><li>&lt;b&gt;</li>\n</ul>\n"
+    );
+}
+
+#[derive(Template)]
+#[template(path = "tests/sidebar.erst", type = "html")]
+pub struct Sidebar<'a> {
+    pub title: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "tests/literals.erst")]
+pub struct Note;
+
+#[derive(Template)]
+#[template(path = "tests/nested.erst", type = "html")]
+pub struct Nested<'a> {
+    pub sidebar: Box<Sidebar<'a>>,
+    pub note: Note,
+}
+
+#[test]
+fn nested_templates() {
+    let nested = Nested { sidebar: Box::new(Sidebar { title: "<b>" }), note: Note };
+    assert_eq!(
+        nested.to_string(),
+        concat!(
+            r#"<div class="sidebar">&lt;b&gt;</div>|<div class="sidebar">&lt;b&gt;</div>|"#,
+            "&lt;% for x in items %&gt;\n&lt;%= x %&gt;\n&lt;% end %&gt;\n|",
+            r#"&lt;div class=&quot;sidebar&quot;&gt;&amp;lt;b&amp;gt;&lt;&#x2f;div&gt;"#,
+            "\n"
+        )
+    );
+}
+
+#[test]
+fn trait_objects() {
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    let sidebar = Sidebar { title: "a" };
+    let templates: Vec<Box<dyn Template>> = vec![
+        Box::new(Sidebar { title: "b" }),
+        Box::new(&sidebar),
+        Box::new(Rc::new(Literals)),
+        Box::new(Arc::new(RawOutput("<"))),
+    ];
+
+    let rendered = templates.iter().map(|x| x.render().unwrap()).collect::<Vec<_>>();
+    assert_eq!(
+        rendered,
+        vec![
+            r#"<div class="sidebar">b</div>"#,
+            r#"<div class="sidebar">a</div>"#,
+            "<% for x in items %>\n<%= x %>\n<% end %>\n",
+            "&lt;|<|<\n",
+        ]
+    );
+    assert_eq!(templates[0].to_string(), rendered[0]);
+    assert_eq!(templates[3].size_hint(), 1024);
+}
+
+#[derive(Template)]
+#[template(source = "<p>Hello, <%= self.0 %>!</p>", type = "html")]
+pub struct Inline<'a>(&'a str);
+
+#[derive(Template)]
+#[template(
+    source = "<ul><% for item in &self.items { %><%! include \"tests/partials/item.erst\" %><% } %></ul>"
+)]
+pub struct InlineInclude<'a> {
+    pub items: Vec<&'a str>,
+}
+
+#[test]
+fn inline_source() {
+    assert_eq!(Inline("<World>").to_string(), "<p>Hello, &lt;World&gt;!</p>");
+    assert_eq!(
+        InlineInclude { items: vec!["a", "b"] }.to_string(),
+        "<ul><li>a</li><li>b</li></ul>"
+    );
+}
+
+/// Escapes Markdown emphasis and code markers
+#[derive(Default)]
+pub struct Emphasis;
+
+impl erst::Escaper for Emphasis {
+    fn escape(&self, input: &str, writer: &mut dyn std::fmt::Write) -> std::fmt::Result {
+        for chr in input.chars() {
+            if let '*' | '_' | '`' = chr {
+                writer.write_char('\\')?;
+            }
+            writer.write_char(chr)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Template)]
+#[template(source = "_<%= self.0 %>_", escape = "Emphasis")]
+pub struct Italic<'a>(&'a str);
+
+#[derive(Template)]
+#[template(
+    source = "<%= self.0 %> <%= self.1 %> <%= erst::Raw(\"*raw*\") %> <%= self.2 %>",
+    escape = "Emphasis"
+)]
+pub struct Line<'a>(Italic<'a>, &'a str, Sidebar<'a>);
+
+#[test]
+fn custom_escaper() {
+    assert_eq!(Italic("a_b").to_string(), "_a\\_b_");
+    assert_eq!(
+        Line(Italic("*"), "`x`", Sidebar { title: "_" }).to_string(),
+        "_\\*_ \\`x\\` *raw* <div class=\"sidebar\">\\_</div>"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "<entry><title><%= self.0 %></title><content><%= erst::Cdata(self.1) %></content></entry>",
+    type = "xml"
+)]
+pub struct Entry<'a>(&'a str, &'a str);
+
+#[derive(Template)]
+#[template(source = "<feed><%= self.0 %></feed>", type = "xml")]
+pub struct Feed<'a>(Entry<'a>);
+
+#[test]
+fn xml() {
+    assert_eq!(
+        Entry("<a href='x'>\"Tom\" & co/\u{1}\u{FFFF}\t</a>", "x]]>y\u{0}").to_string(),
+        concat!(
+            "<entry><title>&lt;a href=&apos;x&apos;&gt;&quot;Tom&quot; &amp; co/\u{FFFD}\u{FFFD}\t&lt;/a&gt;</title>",
+            "<content><![CDATA[x]]]]><![CDATA[>y\u{FFFD}]]></content></entry>"
+        )
+    );
+    assert_eq!(
+        Feed(Entry("<", "<")).to_string(),
+        "<feed><entry><title>&lt;</title><content><![CDATA[<]]></content></entry></feed>"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = r#"{"name": "<%= self.name %>", "tags": <%= erst::Json(&self.tags) %>, "score": <%= erst::Json(self.score) %>}"#,
+    type = "json"
+)]
+pub struct User<'a> {
+    pub name: &'a str,
+    pub tags: Vec<&'a str>,
+    pub score: Option<f64>,
+}
+
+#[derive(Template)]
+#[template(source = r#"{"user": <%= self.0 %>}"#, type = "json")]
+pub struct Response<'a>(User<'a>);
+
+#[test]
+fn json() {
+    let user = User {
+        name: "\"Bob\" \\ </script>\n\u{1}\u{2028}",
+        tags: vec!["a", "b\"c"],
+        score: Some(1.0),
+    };
+    assert_eq!(
+        user.to_string(),
+        r#"{"name": "\"Bob\" \\ \u003c/script\u003e\n\u0001\u2028", "tags": ["a","b\"c"], "score": 1.0}"#
+    );
+    assert_eq!(
+        Response(User { name: "&", tags: vec![], score: Some(f64::NAN) }).to_string(),
+        r#"{"user": {"name": "\u0026", "tags": [], "score": null}}"#
+    );
+    assert_eq!(erst::Json(Some('x')).to_string(), r#""x""#);
+    assert_eq!(erst::Json(None::<u8>).to_string(), "null");
+    assert_eq!(erst::Json(&[-1i64, 2][..]).to_string(), "[-1,2]");
+    assert_eq!(erst::Json(true).to_string(), "true");
+}
+
+#[derive(Template)]
+#[template(
+    source = "<script>var name = '<%= erst::Js(self.0) %>';</script><style>p::after { content: '<%= erst::Css(self.0) %>'; }</style><p><%= self.0 %></p>",
+    type = "html"
+)]
+pub struct Widget<'a>(&'a str);
+
+#[derive(Template)]
+#[template(source = "var name = '<%= self.0 %>';", type = "js")]
+pub struct Script<'a>(&'a str);
+
+#[derive(Template)]
+#[template(source = "p::after { content: '<%= self.0 %>'; }", type = "css")]
+pub struct Style<'a>(&'a str);
+
+#[test]
+fn js_and_css() {
+    assert_eq!(
+        Widget("</script>'").to_string(),
+        concat!(
+            r"<script>var name = '\x3c\x2fscript\x3e\x27';</script>",
+            r"<style>p::after { content: '\3c \2f script\3e \27 '; }</style>",
+            "<p>&lt;&#x2f;script&gt;&#x27;</p>"
+        )
+    );
+    assert_eq!(Script("';alert(1)//").to_string(), r"var name = '\x27\x3balert\x281\x29\x2f\x2f';");
+    assert_eq!(Style("'}").to_string(), r"p::after { content: '\27 \7d '; }");
+}
+
+#[derive(Template)]
+#[template(path = "tests/contexts.erst", type = "html")]
+pub struct Contexts<'a> {
+    pub url: &'a str,
+    pub text: &'a str,
+    pub page: u32,
+    pub tags: Vec<&'a str>,
+}
+
+#[test]
+fn html_contexts() {
+    let contexts = Contexts { url: "/a b?x=1&y=\"2\"", text: "</x>'", page: 2, tags: vec!["a"] };
+    assert_eq!(
+        contexts.to_string(),
+        r#"<a href="/a%20b?x=1&amp;y=%222%22" title="&lt;&#x2f;x&gt;&#x27;">link</a>
+<a href="/search?q=%3C%2Fx%3E%27&amp;page=2" style="color: \3c \2f x\3e \27 ">search</a>
+<button onclick="greet('\x3c\x2fx\x3e\x27')">hi</button>
+<!-- &lt;&#x2f;x&gt;&#x27; -->
+<script>
+    // '\x3c\x2fx\x3e\x27'
+    var text = "\x3c\x2fx\x3e\x27", page = 2, tags = ["a"];
+    var title = "\u003c/x\u003e\u0027", shown = true, last = "a";
+    var quote = /['"]/g, half = 2 / 2, next = '\x3c\x2fx\x3e\x27';
+</script>
+<style>p::after { content: "\3c \2f x\3e \27 "; }</style>
+<textarea>&lt;&#x2f;x&gt;&#x27;</textarea>
+<p>&lt;&#x2f;x&gt;&#x27;</p>
+"#
+    );
+}
+
+#[test]
+fn unsafe_urls() {
+    for url in
+        &["javascript:alert(1)", "JavaScript:alert(1)", "data:text/html,<script>", "vbscript:x"]
+    {
+        let contexts = Contexts { url, text: "", page: 0, tags: vec![] };
+        assert!(contexts.to_string().starts_with(r#"<a href="about:invalid#erst-unsafe-url""#));
+    }
+    for url in &["https://example.com/a?b#c", "mailto:a@example.com", "/a:b", "?a=b:c", "#x"] {
+        let contexts = Contexts { url, text: "", page: 0, tags: vec![] };
+        assert!(contexts.to_string().starts_with(&format!(r#"<a href="{}""#, url)));
+    }
+}
+
+#[derive(Template)]
+#[template(
+    source = r#"<a href="/files/<%= erst::UrlPath(self.path) %>?<%= self.query %>"><%= self.query %></a><a href="<%= self.query %>">?</a><a href="/u/<%= erst::UrlComponent(self.path) %>/<%= self.path %>">u</a>"#,
+    type = "html"
+)]
+pub struct Links<'a> {
+    pub path: &'a str,
+    pub query: erst::Query,
+}
+
+#[derive(Template)]
+#[template(
+    source = "https://example.com/<%= erst::UrlPath(self.0) %>/<%= self.0 %>?<%= self.1 %>",
+    type = "url"
+)]
+pub struct Url<'a>(&'a str, erst::Query);
+
+#[test]
+fn urls() {
+    let query = erst::Query::new().param("q", "a&b c").param("page", 2);
+    assert_eq!(
+        Links { path: "a b/c\"d", query: query.clone() }.to_string(),
+        concat!(
+            r#"<a href="/files/a%20b/c%22d?q=a%26b%20c&amp;page=2">q=a%26b%20c&amp;page=2</a>"#,
+            r#"<a href="q=a%26b%20c&amp;page=2">?</a>"#,
+            r#"<a href="/u/a%20b%2Fc%22d/a%20b%2Fc%22d">u</a>"#
+        )
+    );
+    assert_eq!(
+        Url("a/é", query).to_string(),
+        "https://example.com/a/%C3%A9/a%2F%C3%A9?q=a%26b%20c&page=2"
+    );
+    assert_eq!(
+        vec![("k", "v=1"), ("k2", "")].into_iter().collect::<erst::Query>().to_string(),
+        "k=v%3D1&k2="
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "cp <%= self.0 %> <%= erst::ShellQuote(self.1) %> && echo <%= erst::Raw(\"$HOME\") %>",
+    type = "sh"
+)]
+pub struct Deploy<'a>(&'a str, &'a str);
+
+#[derive(Template)]
+#[template(source = "INSERT INTO users (name) VALUES (<%= self.0 %>);", type = "sql")]
+pub struct Insert<'a>(&'a str);
+
+#[test]
+fn shell_and_sql() {
+    assert_eq!(Deploy("it's", "a b").to_string(), r"cp 'it'\''s' 'a b' && echo $HOME");
+    assert_eq!(Insert("O'Brien").to_string(), "INSERT INTO users (name) VALUES ('O''Brien');");
+    assert!(Insert("\0").render().is_err());
+}
+
+#[derive(Template)]
+#[template(
+    source = "pub const <%= erst::RustIdent(self.0) %>: &str = \"<%= self.1 %>\";\npub const ALT: &str = <%= erst::RustStr(self.1) %>;",
+    type = "rust"
+)]
+pub struct Constant<'a>(&'a str, &'a str);
+
+#[test]
+fn rust() {
+    let rendered = Constant("type", "say \"hi\"\n").to_string();
+    assert_eq!(
+        rendered,
+        "pub const r#type: &str = \"say \\\"hi\\\"\\n\";\npub const ALT: &str = \"say \\\"hi\\\"\\n\";"
+    );
+    #[cfg(feature = "rust")]
+    assert!(erst::check_rust(&rendered).is_ok());
+}
+
+#[derive(Template)]
+#[template(source = "\\section{<%= self.0 %>} <%== self.1 %>", type = "latex")]
+pub struct Report<'a>(&'a str, &'a str);
+
+#[derive(Template)]
+#[template(source = "# <%= self.0 %>\n\n<%= erst::Raw(self.1) %>", type = "markdown")]
+pub struct Readme<'a>(&'a str, &'a str);
+
+#[test]
+fn latex_and_markdown() {
+    assert_eq!(
+        Report("100% & co_op", "\\emph{x}").to_string(),
+        "\\section{100\\% \\& co\\_op} \\emph{x}"
+    );
+    assert_eq!(Readme("*erst* [docs]", "*bold*").to_string(), "# \\*erst\\* \\[docs\\]\n\n*bold*");
+}
+
+#[derive(Template)]
+#[template(source = "<%= self.0 %>,<%= self.1 %>\n", type = "csv")]
+#[derive(Clone)]
+pub struct CsvRow<'a>(&'a str, u32);
+
+#[derive(Template)]
+#[template(source = "<%= self.0 %>;<%= self.1 %>\n", type = "csv", delimiter = ';')]
+pub struct SemicolonRow<'a>(&'a str, &'a str);
+
+#[test]
+fn csv() {
+    assert_eq!(CsvRow("Acme", 3).to_string(), "Acme,3\n");
+    assert_eq!(CsvRow("Smith, \"Jo\"", 3).to_string(), "\"Smith, \"\"Jo\"\"\",3\n");
+    assert_eq!(SemicolonRow("a,b", "c;d").to_string(), "a,b;\"c;d\"\n");
+}
+
+#[derive(Template)]
+#[template(
+    source = "<%= self.0 %> <%= self.1 %> <%= self.2 %> <%= self.3 %> <%= &*self.0 %> <%= erst::Raw(self.1) %>",
+    type = "html"
+)]
+pub struct Strings<'a>(String, &'a str, std::borrow::Cow<'a, str>, std::rc::Rc<str>);
+
+#[test]
+fn html_strings() {
+    let rendered = Strings("<a>".into(), "b & c", "'d'".into(), "e/f".into()).to_string();
+    assert_eq!(rendered, "&lt;a&gt; b &amp; c &#x27;d&#x27; e&#x2f;f &lt;a&gt; b & c");
+}
+
+/// A writer whose peer has gone away
+struct Closed;
+
+impl std::io::Write for Closed {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn render_to_io() {
+    let rows = vec![CsvRow("Acme", 3); 10_000];
+
+    let mut out = Vec::new();
+    for row in &rows {
+        row.render_to_io(&mut out).unwrap();
+    }
+    assert_eq!(out, "Acme,3\n".repeat(10_000).into_bytes());
+
+    let error = Insert("O'Brien").render_to_io(&mut Closed).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::BrokenPipe);
+
+    // Nothing is written when the template fails
+    let mut out = Vec::new();
+    let error = Insert("\0").render_to_io(&mut out).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::Other);
+    assert!(out.is_empty());
+
+    let path = std::env::temp_dir().join(format!("erst-write-to-file-{}.sql", std::process::id()));
+    let page: Box<dyn Template> = Box::new(Insert("O'Brien"));
+    page.write_to_file(&path).unwrap();
+    let written = std::fs::read_to_string(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(written.unwrap(), "INSERT INTO users (name) VALUES ('O''Brien');");
+
+    // A failed render leaves the existing file as it was
+    let dir = std::env::temp_dir().join(format!("erst-write-to-file-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("insert.sql");
+    std::fs::write(&path, "old").unwrap();
+    assert!(Insert("\0").write_to_file(&path).is_err());
+    let written = std::fs::read_to_string(&path);
+    let files = std::fs::read_dir(&dir).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!((written.unwrap().as_str(), files), ("old", 1));
+
+    // Concurrent writes to the same path each replace the file with a complete page
+    let dir =
+        std::env::temp_dir().join(format!("erst-write-to-file-{}-threads", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("list.html");
+    let threads = (1..=8)
+        .map(|count| {
+            let path = path.clone();
+            std::thread::spawn(move || List(count * 1000).write_to_file(&path).unwrap())
+        })
+        .collect::<Vec<_>>();
+    threads.into_iter().for_each(|x| x.join().unwrap());
+    let written = std::fs::read_to_string(&path);
+    let files = std::fs::read_dir(&dir).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let written = written.unwrap();
+    assert!((1..=8).any(|count| written == List(count * 1000).to_string()));
+    assert_eq!(files, 1);
+}
+
+#[derive(Template)]
+#[template(
+    source = "<ul><% for idx in 0..self.0 { %><li>é <%= idx %></li><% } %></ul>",
+    type = "html"
+)]
+pub struct List(u32);
+
+#[test]
+fn chunks() {
+    let expected = List(1000).render().unwrap();
+
+    for &chunk_size in &[1, 2, 7, 64, 100_000] {
+        let mut chunks = Vec::new();
+        List(1000)
+            .render_chunks(chunk_size, &mut |chunk| {
+                chunks.push(chunk);
+                Ok(())
+            })
+            .unwrap();
+        assert!(chunks.iter().all(|x| x.len() <= chunk_size.max(2)));
+        assert_eq!(chunks.concat(), expected);
+
+        let chunks = List(1000).into_chunks(chunk_size).collect::<Result<Vec<_>, _>>().unwrap();
+        assert!(chunks.iter().all(|x| x.len() <= chunk_size.max(2)));
+        assert_eq!(chunks.concat(), expected);
+    }
+
+    assert_eq!(List(1000).into_chunks(64).take(2).count(), 2);
+    assert!(Insert("\0").into_chunks(64).last().unwrap().is_err());
+
+    let chunks = Panicking(1000).into_chunks(64).collect::<Vec<_>>();
+    assert!(chunks.len() > 1);
+    assert!(chunks.last().unwrap().is_err());
+}
+
+pub struct Panic;
+
+impl std::fmt::Display for Panic {
+    fn fmt(&self, _: &mut std::fmt::Formatter) -> std::fmt::Result {
+        panic!("rendering panicked")
+    }
+}
+
+#[derive(Template)]
+#[template(source = "<%= List(self.0) %><%= Panic %>", type = "html")]
+pub struct Panicking(u32);
+
+#[cfg(feature = "async")]
+#[test]
+fn stream() {
+    use futures::StreamExt;
+
+    let chunks = futures::executor::block_on(List(1000).into_stream(64).collect::<Vec<_>>());
+    assert!(chunks.len() > 1);
+    assert_eq!(
+        chunks.into_iter().collect::<Result<String, _>>().unwrap(),
+        List(1000).render().unwrap()
+    );
+
+    let chunks = futures::executor::block_on(Panicking(1000).into_stream(64).collect::<Vec<_>>());
+    assert!(chunks.last().unwrap().is_err());
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PriceError {
+    Fmt,
+    Parse(String),
+}
+
+impl From<std::fmt::Error> for PriceError {
+    fn from(_: std::fmt::Error) -> Self {
+        PriceError::Fmt
+    }
+}
+
+fn price(input: &str) -> Result<f64, PriceError> {
+    input.parse().map_err(|_| PriceError::Parse(input.to_string()))
+}
+
+#[derive(Template)]
+#[template(
+    source = "<% for item in self.0 { %><%= item %>: <%= format!(\"{:.2}\", price(item)?) %>\n<% } %><%= self.1 %>",
+    type = "sql",
+    error = "PriceError"
+)]
+pub struct Prices<'a>(&'a [&'a str], &'a str);
+
+#[test]
+fn fallible() {
+    use erst::TryTemplate;
+
+    let prices = Prices(&["1", "2.5"], "done");
+    assert_eq!(prices.try_render().unwrap(), "'1': '1.00'\n'2.5': '2.50'\n'done'");
+    assert_eq!(prices.render().unwrap(), prices.try_render().unwrap());
+
+    let prices = Prices(&["1", "x"], "done");
+    assert_eq!(prices.try_render(), Err(PriceError::Parse("x".to_string())));
+    assert!(prices.render().is_err());
+
+    assert_eq!(Prices(&[], "\0").try_render(), Err(PriceError::Fmt));
+}
+
+/// A value whose `Display` implementation fails if it holds `true`
+pub struct Failing(bool);
+
+impl std::fmt::Display for Failing {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.0 {
+            Err(std::fmt::Error)
+        } else {
+            f.write_str("ok")
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "tests/includes.erst", type = "html")]
+pub struct FailingItems {
+    pub items: Vec<Failing>,
+}
+
+#[derive(Template)]
+#[template(source = "<p>\n  <%== self.0 %>\n</p>")]
+pub struct FailingInline(Failing);
+
+#[test]
+fn render_errors() {
+    let rendered = FailingItems { items: vec![Failing(false)] }.render();
+    assert_eq!(rendered.unwrap(), "<ul>\n<li>ok</li>\n</ul>\n");
+
+    let error = FailingItems { items: vec![Failing(false), Failing(true)] }.render().unwrap_err();
+    assert_eq!(error.path(), Some("tests/partials/item.erst"));
+    assert_eq!(error.line(), Some(1));
+    assert_eq!(error.to_string(), "error rendering template `tests/partials/item.erst` at line 1");
+
+    let error = FailingInline(Failing(true)).render().unwrap_err();
+    assert_eq!((error.path(), error.line()), (Some("<FailingInline source>"), Some(2)));
+
+    let error = FailingInline(Failing(true)).render_to_io(&mut Vec::new()).unwrap_err();
+    let error = error.get_ref().and_then(|x| x.downcast_ref::<erst::RenderError>()).unwrap();
+    assert_eq!(error.line(), Some(2));
+
+    let error = Prices(&["1", "x"], "done").render().unwrap_err();
+    assert_eq!((error.path(), error.line()), (Some("<Prices source>"), Some(1)));
+
+    // `Display` still reports a plain `fmt::Error`
+    let mut out = String::new();
+    assert!(std::fmt::Write::write_fmt(&mut out, format_args!("{}", FailingInline(Failing(true))))
+        .is_err());
+}
+
+#[derive(Template)]
+#[template(source = "<script>var r = /'/; var s = '<%= self.0 %>';</script>", type = "html")]
+pub struct AfterRegex(&'static str);
+
+#[derive(Template)]
+#[template(
+    source = "<script>function f(s) { return /[/']/.test(s) } var s = '<%= self.0 %>';</script>",
+    type = "html"
+)]
+pub struct AfterRegexClass(&'static str);
+
+#[test]
+fn script_regex() {
+    assert_eq!(
+        AfterRegex("';alert(1)//").to_string(),
+        r"<script>var r = /'/; var s = '\x27\x3balert\x281\x29\x2f\x2f';</script>"
+    );
+    assert_eq!(
+        AfterRegexClass("';alert(1)//").to_string(),
+        r"<script>function f(s) { return /[/']/.test(s) } var s = '\x27\x3balert\x281\x29\x2f\x2f';</script>"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "<a title=\"<%= self.0 %>\"><%= self.0 %></a><title><%= self.0 %></title><!-- <%= erst::Raw(\"-->\") %> -->",
+    type = "html"
+)]
+pub struct MarkupInAttributes<'a>(Sidebar<'a>);
+
+#[test]
+fn markup_in_attributes() {
+    assert_eq!(
+        MarkupInAttributes(Sidebar { title: "<b>" }).to_string(),
+        "<a title=\"&lt;div class=&quot;sidebar&quot;&gt;&amp;lt;b&amp;gt;&lt;&#x2f;div&gt;\">\
+         <div class=\"sidebar\">&lt;b&gt;</div></a>\
+         <title>&lt;div class=&quot;sidebar&quot;&gt;&amp;lt;b&amp;gt;&lt;&#x2f;div&gt;</title><!-- --&gt; -->"
+    );
+}
@@ -0,0 +1,7 @@
+#[test]
+fn ui() {
+    // trybuild drops the `:line:col` after template paths, so these only check the messages and the annotated source
+    // lines; the locations themselves are tested in erst-derive's `diagnostic` module
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(source = "", error = "not an error")]
+pub struct Container;
+
+fn main() {}
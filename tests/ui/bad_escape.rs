@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(source = "", escape = "not an escaper")]
+pub struct Container;
+
+fn main() {}
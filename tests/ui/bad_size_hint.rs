@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(path = "simple.erst", size_hint = "large")]
+pub struct Container;
+
+fn main() {}
@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(source = "", type = "sql", delimiter = ';')]
+pub struct Row;
+
+fn main() {}
@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(source = "<button onclick=\"run(<%= self.0 %>)\">run</button>", type = "html")]
+pub struct Button(String);
+
+fn main() {}
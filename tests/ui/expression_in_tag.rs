@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(source = "<input <%= self.0 %>>", type = "html")]
+pub struct Input(String);
+
+fn main() {}
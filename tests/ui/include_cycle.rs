@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(path = "ui/include_cycle_a.erst")]
+pub struct Container;
+
+fn main() {}
@@ -0,0 +1,6 @@
+use erst::Template;
+
+#[derive(Template)]
+pub struct Container;
+
+fn main() {}
@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(type = "html")]
+pub struct Container;
+
+fn main() {}
@@ -0,0 +1,15 @@
+use erst::Template;
+
+pub struct Id(u32);
+
+impl std::fmt::Display for Id {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Template)]
+#[template(source = "<script>var id = <%= self.0 %>;</script>", type = "html")]
+pub struct Script(Id);
+
+fn main() {}
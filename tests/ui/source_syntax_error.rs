@@ -0,0 +1,9 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(source = "Hello <%= self.name. %>!")]
+pub struct Greeting {
+    pub name: String,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(path = "ui/syntax_error.erst")]
+pub struct Container {
+    pub collection: Vec<String>,
+}
+
+fn main() {}
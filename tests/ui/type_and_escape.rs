@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(source = "", type = "html", escape = "erst::HtmlEscaper")]
+pub struct Container;
+
+fn main() {}
@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(path = "ui/unclosed_block.erst")]
+pub struct Container;
+
+fn main() {}
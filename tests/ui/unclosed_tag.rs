@@ -0,0 +1,9 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(path = "ui/unclosed_tag.erst")]
+pub struct Container {
+    pub collection: Vec<String>,
+}
+
+fn main() {}
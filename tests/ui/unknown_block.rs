@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(path = "ui/unknown_block.erst")]
+pub struct Page;
+
+fn main() {}
@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(path = "simple.erst", paht = "simple.erst")]
+pub struct Container;
+
+fn main() {}
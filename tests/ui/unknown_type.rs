@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(path = "simple.erst", type = "htm")]
+pub struct Container;
+
+fn main() {}
@@ -0,0 +1,7 @@
+use erst::Template;
+
+#[derive(Template)]
+#[template(source = "<a href=<%= self.0 %>>link</a>", type = "html")]
+pub struct Link(String);
+
+fn main() {}